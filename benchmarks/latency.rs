@@ -1,23 +1,14 @@
-#![feature(phase)]
-#![feature(macro_rules)]
-#[phase(syntax, link)]
-
+#[macro_use]
 extern crate log;
-extern crate sync;
-extern crate libc;
-extern crate time;
 extern crate turbine;
 
-use turbine::{Turbine, Slot, WaitStrategy, BusyWait};
+use turbine::{Turbine, Slot, BusyWait, TurbineError};
 
-use std::io::timer;
-use std::fmt;
-use std::task::{TaskBuilder};
-use std::sync::Future;
-use time::precise_time_ns;
-use std::num::{abs,pow};
-use libc::funcs::posix88::unistd::usleep;
-use std::io::File;
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::fs::File;
+use std::io::Write;
 
 struct TestSlotU64 {
     pub value: u64
@@ -26,74 +17,77 @@ struct TestSlotU64 {
 impl Slot for TestSlotU64 {
     fn new() -> TestSlotU64 {
         TestSlotU64 {
-            value: -1	// Negative value here helps catch bugs since counts will be wrong
+            value: u64::MAX	// Max value here helps catch bugs since counts will be wrong
+        }
+    }
+}
+
+// Bucket a latency (in ns) into a log2 histogram
+fn record(latencies: &mut [u64], total: u64) {
+    for (i, bucket) in latencies.iter_mut().enumerate().skip(1) {
+        if 2u64.saturating_pow(i as u32) > total {
+            *bucket += 1;
+            break;
         }
     }
 }
 
 fn bench_turbine_latency() {
-    let path = Path::new("turbine_latency.csv");
-    let mut file = match File::create(&path) {
-            Err(why) => fail!("couldn't create file: {}", why.desc),
+    let mut file = match File::create("turbine_latency.csv") {
+            Err(why) => panic!("couldn't create file: {}", why),
             Ok(file) => file
     };
 
-    let mut t: Turbine<TestSlotU64> = Turbine::new(1048576);
+    let mut t: Turbine<TestSlotU64> = Turbine::new(1048576).unwrap();
     let e1 = t.ep_new().unwrap();
 
-    let event_processor = t.ep_finalize(e1);
+    let event_processor = t.ep_finalize(e1).unwrap();
     let (tx, rx): (Sender<Vec<u64>>, Receiver<Vec<u64>>) = channel();
 
-    let mut future = Future::spawn(proc() {
-        let mut counter: int = 0;
-        let mut latencies: Vec<u64> = Vec::from_fn(100, |i| 0);
+    // Both threads measure time as an offset from the same instant
+    let epoch = Instant::now();
 
-        event_processor.start::<BusyWait>(|data: &[TestSlotU64]| -> Result<(),()> {
-            for d in data.iter() {
-                let end = precise_time_ns();
-                let total = abs((end - d.value) as i64) as u64;
+    thread::spawn(move || {
+        let mut counter: isize = 0;
+        let mut latencies: Vec<u64> = vec![0; 100];
 
-                for i in range(1u,100) {
-                    if pow(2, i) > total {
-                        *latencies.get_mut(i) += 1;
-                        break;
-                    }
-                }
+        event_processor.start::<BusyWait>(|data: &[TestSlotU64]| -> Result<(), TurbineError> {
+            for d in data.iter() {
+                let end = epoch.elapsed().as_nanos() as u64;
+                let total = end.abs_diff(d.value);
+                record(&mut latencies, total);
 
                 //error!("{}, {}, {}", d.value, end, total);
                 counter += 1;
             }
 
             if counter == 50000000 {
-                return Err(());
+                Err(TurbineError::Shutdown)
             } else {
-                return Ok(());
+                Ok(())
             }
 
         });
-        tx.send(latencies);
+        tx.send(latencies).unwrap();
     });
 
-    for i in range(0i, 50000000) {
+    for _ in 0isize..50000000 {
         let mut s: TestSlotU64 = Slot::new();
-        s.value = precise_time_ns();
-        t.write(s);
+        s.value = epoch.elapsed().as_nanos() as u64;
+        t.write(s).unwrap();
 
-        unsafe { usleep(10); }	//sleep for 1 microseconds
+        thread::sleep(Duration::from_micros(10));	//sleep for 10 microseconds
     }
 
-    let latencies = match rx.recv_opt() {
+    let latencies = match rx.recv() {
         Ok(l) => l,
-        Err(_) => fail!("No latencies were returned!")
+        Err(_) => panic!("No latencies were returned!")
     };
 
 
     for l in latencies.iter() {
-        match file.write_line(l.to_string().as_slice()) {
-            Err(why) => {
-                fail!("couldn't write to file: {}", why.desc)
-            },
-            Ok(_) => {}
+        if let Err(why) = writeln!(file, "{}", l) {
+            panic!("couldn't write to file: {}", why)
         }
     }
 
@@ -101,50 +95,41 @@ fn bench_turbine_latency() {
 
 
 fn bench_chan_latency() {
-    let path = Path::new("chan_latency.csv");
-    let mut file = match File::create(&path) {
-            Err(why) => fail!("couldn't create file: {}", why.desc),
+    let mut file = match File::create("chan_latency.csv") {
+            Err(why) => panic!("couldn't create file: {}", why),
             Ok(file) => file
     };
 
     let (tx_bench, rx_bench): (Sender<u64>, Receiver<u64>) = channel();
 
+    // Both threads measure time as an offset from the same instant
+    let epoch = Instant::now();
 
-    let mut future = Future::spawn(proc() {
-        for _ in range(0i, 50000000)  {
-            let x = precise_time_ns();
-            tx_bench.send(x);
-            unsafe { usleep(10); }	//sleep for 1 microseconds
+    let future = thread::spawn(move || {
+        for _ in 0isize..50000000  {
+            let x = epoch.elapsed().as_nanos() as u64;
+            tx_bench.send(x).unwrap();
+            thread::sleep(Duration::from_micros(10));	//sleep for 10 microseconds
         }
 
     });
 
-    let mut counter: int = 0;
-    let mut latencies: Vec<u64> = Vec::from_fn(100, |i| 0);
-
-    for i in range(0i, 50000000) {
-        counter += 1;
-        let end = precise_time_ns();
-        let start = rx_bench.recv();
-        let total = abs((end - start) as i64) as u64;	// because ticks can go backwards between different cores
-        for i in range(1u,100) {
-            if pow(2, i) > total {
-                *latencies.get_mut(i) += 1;
-                break;
-            }
-        }
+    let mut latencies: Vec<u64> = vec![0; 100];
+
+    for _ in 0isize..50000000 {
+        let start = rx_bench.recv().unwrap();
+        let end = epoch.elapsed().as_nanos() as u64;
+        let total = end.abs_diff(start);
+        record(&mut latencies, total);
     }
 
     for l in latencies.iter() {
-        match file.write_line(l.to_string().as_slice()) {
-            Err(why) => {
-                fail!("couldn't write to file: {}", why.desc)
-            },
-            Ok(_) => {}
+        if let Err(why) = writeln!(file, "{}", l) {
+            panic!("couldn't write to file: {}", why)
         }
     }
 
-    future.get();
+    future.join().unwrap();
 }
 
 fn main() {
@@ -152,4 +137,3 @@ fn main() {
     error!("Starting channels");
     bench_chan_latency();
 }
-
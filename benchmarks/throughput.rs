@@ -1,24 +1,17 @@
-#![feature(phase)]
-#![feature(macro_rules)]
-#[phase(syntax, link)]
-
+#[macro_use]
 extern crate log;
-extern crate sync;
-
-extern crate time;
 extern crate turbine;
 
-use turbine::{Turbine, Slot, WaitStrategy, BusyWait};
+use turbine::{Turbine, Slot, BusyWait, TurbineError};
 
-use std::io::timer;
-use std::fmt;
-use std::task::{TaskBuilder};
-use std::sync::Future;
-use time::precise_time_ns;
-use std::io::File;
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+use std::time::Instant;
+use std::fs::File;
+use std::io::Write;
 
 struct TestSlot {
-    pub value: int
+    pub value: isize
 }
 
 impl Slot for TestSlot {
@@ -31,102 +24,93 @@ impl Slot for TestSlot {
 
 fn bench_chan_100m() -> f32 {
 
-    let (tx_bench, rx_bench): (Sender<int>, Receiver<int>) = channel();
+    let (tx_bench, rx_bench): (Sender<isize>, Receiver<isize>) = channel();
 
-    let mut future = Future::spawn(proc() {
-        for _ in range(0i, 100000000)  {
-            tx_bench.send(1);
+    let future = thread::spawn(move || {
+        for _ in 0isize..100000000  {
+            tx_bench.send(1).unwrap();
         }
 
     });
 
-    let start = precise_time_ns();
+    let start = Instant::now();
     let mut counter = 0;
-    for i in range(0i, 100000000) {
-        counter += rx_bench.recv();
+    for _ in 0isize..100000000 {
+        counter += rx_bench.recv().unwrap();
     }
-    let end = precise_time_ns();
+    let elapsed = start.elapsed().as_secs_f32();
 
-    future.get();
+    future.join().unwrap();
+    assert!(counter == 100000000);
 
-    error!("Channel: Total time: {}s", (end-start) as f32 / 1000000000f32);
-    error!("Channel: ops/s: {}", 100000000f32 / ((end-start) as f32 / 1000000000f32));
-    100000000f32 / ((end-start) as f32 / 1000000000f32)
+    error!("Channel: Total time: {}s", elapsed);
+    error!("Channel: ops/s: {}", 100000000f32 / elapsed);
+    100000000f32 / elapsed
 }
 
 
 fn bench_turbine_100m() -> f32 {
-    let mut t: Turbine<TestSlot> = Turbine::new(16384);
+    let mut t: Turbine<TestSlot> = Turbine::new(16384).unwrap();
     let e1 = t.ep_new().unwrap();
 
-    let event_processor = t.ep_finalize(e1);
-    let (tx, rx): (Sender<int>, Receiver<int>) = channel();
+    let event_processor = t.ep_finalize(e1).unwrap();
+    let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
-    let mut future = Future::spawn(proc() {
+    thread::spawn(move || {
         let mut counter = 0;
-        event_processor.start::<BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+        event_processor.start::<BusyWait>(|data: &[TestSlot]| -> Result<(), TurbineError> {
             for d in data.iter() {
                 counter += d.value;
             }
 
             if counter == 100000000 {
-                return Err(());
+                Err(TurbineError::Shutdown)
             } else {
-                return Ok(());
+                Ok(())
             }
 
         });
-        tx.send(1);
+        tx.send(1).unwrap();
     });
 
-    let start = precise_time_ns();
-    for i in range(0i, 100000000) {
+    let start = Instant::now();
+    for _ in 0isize..100000000 {
         let mut s: TestSlot = Slot::new();
         s.value = 1;
-        t.write(s);
+        t.write(s).unwrap();
     }
 
-    rx.recv_opt();
-    let end = precise_time_ns();
+    let _ = rx.recv();
+    let elapsed = start.elapsed().as_secs_f32();
 
 
-     // 1000000000 ns == 1s
-    error!("Turbine: Total time: {}s", (end-start) as f32 / 1000000000f32);
-    error!("Turbine: ops/s: {}", 100000000f32 / ((end-start) as f32 / 1000000000f32));
-    100000000f32 / ((end-start) as f32 / 1000000000f32)
+    error!("Turbine: Total time: {}s", elapsed);
+    error!("Turbine: ops/s: {}", 100000000f32 / elapsed);
+    100000000f32 / elapsed
 }
 
 fn main() {
 
-    let path = Path::new("turbine_throughput.csv");
-    let mut file = match File::create(&path) {
-            Err(why) => fail!("couldn't create file: {}", why.desc),
+    let mut file = match File::create("turbine_throughput.csv") {
+            Err(why) => panic!("couldn't create file: {}", why),
             Ok(file) => file
     };
 
-    for i in range(0i,20) {
+    for _ in 0isize..20 {
         let value = bench_turbine_100m();
-        match file.write_line(value.to_string().as_slice()) {
-            Err(why) => {
-                fail!("couldn't write to file: {}", why.desc)
-            },
-            Ok(_) => {}
+        if let Err(why) = writeln!(file, "{}", value) {
+            panic!("couldn't write to file: {}", why)
         }
     }
 
-    let path = Path::new("chan_throughput.csv");
-    let mut file = match File::create(&path) {
-            Err(why) => fail!("couldn't create file: {}", why.desc),
+    let mut file = match File::create("chan_throughput.csv") {
+            Err(why) => panic!("couldn't create file: {}", why),
             Ok(file) => file
     };
-    for i in range(0i,20) {
+    for _ in 0isize..20 {
         let value =  bench_chan_100m();
-        match file.write_line(value.to_string().as_slice()) {
-            Err(why) => {
-                fail!("couldn't write to file: {}", why.desc)
-            },
-            Ok(_) => {}
+        if let Err(why) = writeln!(file, "{}", value) {
+            panic!("couldn't write to file: {}", why)
         }
     }
 }
-
@@ -0,0 +1,461 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+use std::cmp::min;
+use crate::Turbine;
+use crate::control::Control;
+use crate::error::TurbineError;
+use crate::eventprocessor::EventProcessor;
+use crate::waitstrategy::{WaitStrategy, ProducerWaitStrategy, BusyWait, WriterWait};
+
+/// Length of the header in front of every record: the payload length and the record kind
+const HEADER_LEN: usize = 8;
+
+/// Records start on 8 byte boundaries, so a header never wraps around the end of the buffer
+const ALIGNMENT: usize = 8;
+
+/// The largest buffer whose records' lengths all fit in a header
+const MAX_CAPACITY: usize = u32::MAX as usize;
+
+const KIND_DATA: u32 = 1;
+const KIND_PADDING: u32 = 2;
+
+fn aligned(len: usize) -> usize {
+    (len + ALIGNMENT - 1) & !(ALIGNMENT - 1)
+}
+
+fn write_header(buf: &mut [u8], len: usize, kind: u32) {
+    buf[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+    buf[4..8].copy_from_slice(&kind.to_le_bytes());
+}
+
+fn read_header(buf: &[u8]) -> (usize, u32) {
+    let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    let kind = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    (len, kind)
+}
+
+/// A Turbine which carries variable-length byte records instead of fixed-size Slots
+///
+/// `Turbine<T>` stores one `T` per slot, so variable-size messages have to be boxed
+/// or padded to their worst case.  A ByteTurbine is instead a ring of bytes.  The
+/// writer claims as many contiguous bytes as a record needs and writes it behind a
+/// small length-prefixed header, and EventProcessors receive each record as a `&[u8]`.
+///
+/// Cursors count bytes rather than records, and the writer only ever publishes whole
+/// records.  A record which does not fit before the end of the buffer is moved to
+/// the start, and the gap is filled with a padding record which EPs skip, so records
+/// are always contiguous.  The dependency graph, wait strategies and `Control` all
+/// work exactly as they do for `Turbine`.
+///
+/// ## Example
+///
+///```
+///# use turbine::{ByteTurbine, BusyWait, TurbineError};
+///# use std::thread;
+///let mut t: ByteTurbine = ByteTurbine::new(4096).unwrap();
+///let e1 = t.ep_new().unwrap();
+///let event_processor = t.ep_finalize(e1).unwrap();
+///
+///let handle = thread::spawn(move || {
+///    let mut received = vec![];
+///    event_processor.start::<BusyWait>(|record: &[u8]| -> Result<(), TurbineError> {
+///        received.push(record.to_vec());
+///        if record == b"bye" { Err(TurbineError::Shutdown) } else { Ok(()) }
+///    });
+///    received
+///});
+///
+///t.write(b"hello, world").unwrap();
+///
+///let mut claim = t.claim(3).unwrap();     // Or fill the record in-place
+///claim.copy_from_slice(b"bye");
+///claim.commit();
+///
+///assert!(handle.join().unwrap() == vec![b"hello, world".to_vec(), b"bye".to_vec()]);
+///```
+pub struct ByteTurbine<P = BusyWait> {
+    turbine: Turbine<u8, P>,
+    gate: u64
+}
+
+impl ByteTurbine {
+
+    /// Create a new ByteTurbine with a buffer of `capacity` bytes.
+    ///
+    /// The capacity **must** be a power of two, otherwise `TurbineError::InvalidSize`
+    /// is returned, at least 16 bytes, otherwise `TurbineError::BufferTooSmall` is, and
+    /// at most `u32::MAX` bytes, otherwise `TurbineError::BufferTooLarge` is.  The
+    /// largest record which fits is `max_record_len()` bytes.
+    pub fn new(capacity: usize) -> Result<ByteTurbine, TurbineError> {
+        ByteTurbine::with_wait_strategy(capacity, BusyWait::default())
+    }
+}
+
+impl<P: ProducerWaitStrategy> ByteTurbine<P> {
+
+    /// Create a new ByteTurbine which uses `wait_strategy` whenever the writer has
+    /// to wait for free space, see `Turbine::with_wait_strategy`.
+    pub fn with_wait_strategy(capacity: usize, wait_strategy: P) -> Result<ByteTurbine<P>, TurbineError> {
+        if capacity < 2 * HEADER_LEN {
+            return Err(TurbineError::BufferTooSmall { size: capacity, min: 2 * HEADER_LEN });
+        }
+        // Record lengths are encoded in 32 bits
+        if capacity > MAX_CAPACITY {
+            return Err(TurbineError::BufferTooLarge { size: capacity, max: MAX_CAPACITY });
+        }
+
+        Ok(ByteTurbine {
+            turbine: Turbine::with_wait_strategy(capacity, wait_strategy)?,
+            gate: 0
+        })
+    }
+
+    /// The length of the largest record which fits in the buffer
+    pub fn max_record_len(&self) -> usize {
+        self.turbine.size - HEADER_LEN
+    }
+
+    /// Add a new EventProcessor to the dependency graph, see `Turbine::ep_new`.
+    pub fn ep_new(&mut self) -> Result<usize, TurbineError> {
+        self.turbine.ep_new()
+    }
+
+    /// Make the EventProcessor at `epb_index` depend on `dep`, see `Turbine::ep_depends`.
+    pub fn ep_depends(&mut self, epb_index: usize, dep: usize) -> Result<(), TurbineError> {
+        self.turbine.ep_depends(epb_index, dep)
+    }
+
+    /// Finalize the dependency graph and obtain the EventProcessor at `token`, see
+    /// `Turbine::ep_finalize`.
+    pub fn ep_finalize(&mut self, token: usize) -> Result<ByteEventProcessor, TurbineError> {
+        Ok(ByteEventProcessor { inner: self.turbine.ep_finalize(token)? })
+    }
+
+    /// Obtain a handle which can halt or shut down this ByteTurbine's EventProcessors,
+    /// see `Turbine::control`.
+    pub fn control(&self) -> Control {
+        self.turbine.control()
+    }
+
+    /// Write `record` into the buffer and publish it.
+    ///
+    /// Waits until there is enough free space, like `Turbine::write`.  Returns
+    /// `TurbineError::RecordTooLarge` if the record can never fit, and
+    /// `TurbineError::Poisoned` or `TurbineError::Stopped` if the Turbine has been
+    /// poisoned, or halted or shut down.
+    pub fn write(&mut self, record: &[u8]) -> Result<(), TurbineError> {
+        let mut claim = self.claim(record.len())?;
+        claim.copy_from_slice(record);
+        Ok(())
+    }
+
+    /// Claim `len` contiguous bytes for the next record, to be filled in-place.
+    ///
+    /// The claimed bytes hold whatever was last written there.  The record is
+    /// published when the ByteClaim is committed or dropped.  Like `write`, this waits
+    /// until there is enough free space.
+    pub fn claim(&mut self, len: usize) -> Result<ByteClaim<'_, P>, TurbineError> {
+        if len > self.max_record_len() {
+            return Err(TurbineError::RecordTooLarge { len, max: self.max_record_len() });
+        }
+
+        let capacity = self.turbine.size;
+        let record_len = aligned(HEADER_LEN + len);
+        let position = self.turbine.indexer.position(self.turbine.current_pos);
+
+        // A record which would wrap is moved to the start of the buffer, behind a
+        // padding record which covers the rest of this lap.  The padding is published
+        // on its own first: waiting for the padding and the record together could
+        // need more than the whole buffer to be free, which never happens
+        if position + record_len > capacity {
+            let padding = capacity - position;
+            self.wait_for_space(padding as u64)?;
+
+            // This is safe because `wait_for_space` checked that every EventProcessor
+            // has released these bytes, and they are not published until below
+            let buf = unsafe { self.turbine.ring.get_slice_mut(position, capacity) };
+            write_header(buf, padding - HEADER_LEN, KIND_PADDING);
+            self.publish(padding as u64);
+        }
+
+        let start = self.turbine.indexer.position(self.turbine.current_pos);
+        self.wait_for_space(record_len as u64)?;
+
+        // This is safe for the same reason.  The ByteClaim mutably borrows the
+        // ByteTurbine, so nothing else can be claimed until this record is published
+        let buf = unsafe { self.turbine.ring.get_slice_mut(start, start + record_len) };
+        write_header(buf, len, KIND_DATA);
+        let buf: *mut [u8] = &mut buf[HEADER_LEN..HEADER_LEN + len];
+
+        Ok(ByteClaim {
+            turbine: self,
+            buf,
+            total: record_len as u64
+        })
+    }
+
+    /// Wait until `len` bytes past the writer's position have been released by every
+    /// EventProcessor
+    ///
+    /// The slowest EP cursor is cached in `gate` so that most claims do not need to
+    /// touch the EP Atomics at all.
+    fn wait_for_space(&mut self, len: u64) -> Result<(), TurbineError> {
+        self.turbine.check_bound()?;
+
+        let capacity = self.turbine.size as u64;
+        let end = self.turbine.current_pos + len;
+
+        let mut wait = WriterWait::new();
+        loop {
+            self.turbine.alert.check_running()?;
+            if end - self.gate <= capacity {
+                return Ok(());
+            }
+
+            let mut min_cursor = self.turbine.current_pos;
+            for v in self.turbine.cursors.iter().skip(1) {
+                min_cursor = min(min_cursor, v.load(Ordering::SeqCst) as u64);
+            }
+            self.gate = min_cursor;
+
+            if end - self.gate <= capacity {
+                return Ok(());
+            }
+            wait.wait(&self.turbine.wait_strategy, &self.turbine.notifier);
+        }
+    }
+
+    /// Publish `len` claimed bytes by advancing the root cursor
+    fn publish(&mut self, len: u64) {
+        let turbine = &mut self.turbine;
+        turbine.current_pos += len;
+        turbine.cursors[0].store(turbine.current_pos as usize, Ordering::SeqCst);
+        turbine.notifier.notify_all();
+    }
+}
+
+/// A claimed, unpublished record in a ByteTurbine
+///
+/// Obtained from `ByteTurbine::claim`, and derefs to exactly the number of bytes
+/// which were claimed.  The record is published to EventProcessors when the claim
+/// is committed or dropped.
+pub struct ByteClaim<'a, P: ProducerWaitStrategy = BusyWait> {
+    turbine: &'a mut ByteTurbine<P>,
+    buf: *mut [u8],
+    total: u64
+}
+
+impl<'a, P: ProducerWaitStrategy> ByteClaim<'a, P> {
+
+    /// Publish the claimed record, making it visible to EventProcessors.
+    ///
+    /// This is equivalent to dropping the ByteClaim, but makes the intent explicit.
+    pub fn commit(self) {
+        drop(self);
+    }
+}
+
+impl<'a, P: ProducerWaitStrategy> Deref for ByteClaim<'a, P> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // This is safe because no EventProcessor can read the record until it has
+        // been published, and the ByteClaim holds the only reference to it
+        unsafe { &*self.buf }
+    }
+}
+
+impl<'a, P: ProducerWaitStrategy> DerefMut for ByteClaim<'a, P> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { &mut *self.buf }
+    }
+}
+
+impl<'a, P: ProducerWaitStrategy> Drop for ByteClaim<'a, P> {
+    fn drop(&mut self) {
+        self.turbine.publish(self.total);
+    }
+}
+
+/// An EventProcessor which receives the records of a ByteTurbine
+///
+/// Obtained from `ByteTurbine::ep_finalize`.  Padding records are skipped, so the
+/// closure only ever sees the records which were written.
+pub struct ByteEventProcessor {
+    inner: EventProcessor<u8>
+}
+
+impl ByteEventProcessor {
+
+    /// Begin waiting for records to arrive, calling `f` with each one.
+    ///
+    /// Like `EventProcessor::start`, returning any Err from the closure shuts down
+    /// the EP; by convention it returns Err(TurbineError::Shutdown).  Records after
+    /// the one which returned Err are left unprocessed, so EPs which depend on this
+    /// one never see them either.
+    pub fn start<W: WaitStrategy>(&self, f: impl FnMut(&[u8]) -> Result<(), TurbineError>) {
+        self.start_with(self.wait_strategy::<W>(), f)
+    }
+
+    /// Instantiate WaitStrategy `W` for this EventProcessor with its default configuration.
+    pub fn wait_strategy<W: WaitStrategy>(&self) -> W {
+        self.inner.wait_strategy::<W>()
+    }
+
+    /// Begin waiting for records, using a pre-configured WaitStrategy.
+    ///
+    /// This behaves exactly like `start`, but the supplied WaitStrategy instance is used.
+    pub fn start_with<W: WaitStrategy>(&self, wait_strategy: W, mut f: impl FnMut(&[u8]) -> Result<(), TurbineError>) {
+        // The writer only publishes whole records and never wraps one around the end
+        // of the buffer, so every slice starts and ends on a record boundary.  Each
+        // record is guarded on its own, so the cursor stops at the end of a record
+        // which returned Err, and before one which panicked
+        self.inner.run_loop(wait_strategy, None, |mut data: &[u8], mut sequence: u64, _| -> Result<(), u64> {
+            while !data.is_empty() {
+                let (len, kind) = read_header(data);
+                let total = aligned(HEADER_LEN + len);
+                if kind == KIND_DATA {
+                    let record = &data[HEADER_LEN..HEADER_LEN + len];
+                    self.inner.guard(&data[..total], sequence, || f(record))?;
+                }
+                data = &data[total..];
+                sequence += total as u64;
+            }
+            Ok(())
+        }, |_| Ok(()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{ByteTurbine, HEADER_LEN};
+    use crate::{BusyWait, YieldingWait, TurbineError};
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    fn record(i: usize) -> Vec<u8> {
+        // Lengths cycle through every alignment, so records end at every offset
+        (0..(i * 7) % 61).map(|b| (b + i) as u8).collect()
+    }
+
+    #[test]
+    fn test_records_wrap_with_padding() {
+        let count = 2000;
+        let mut t: ByteTurbine = ByteTurbine::new(256).unwrap();
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        t.ep_depends(e2, e1).unwrap();
+
+        let handles: Vec<_> = [e1, e2].iter().map(|&token| {
+            let event_processor = t.ep_finalize(token).unwrap();
+            thread::spawn(move || {
+                let mut seen = 0;
+                event_processor.start_with(YieldingWait::new(10), |data: &[u8]| -> Result<(), TurbineError> {
+                    assert!(data == record(seen).as_slice());
+                    seen += 1;
+                    Ok(())
+                });
+                seen
+            })
+        }).collect();
+
+        for i in 0..count {
+            t.write(&record(i)).unwrap();
+        }
+        drop(t);
+
+        for h in handles {
+            assert!(h.join().unwrap() == count);
+        }
+    }
+
+    #[test]
+    fn test_claim_and_limits() {
+        assert!(ByteTurbine::new(8).err() == Some(TurbineError::BufferTooSmall { size: 8, min: 16 }));
+        assert!(ByteTurbine::new(100).err() == Some(TurbineError::InvalidSize(100)));
+
+        let mut t: ByteTurbine = ByteTurbine::new(64).unwrap();
+        assert!(t.max_record_len() == 64 - HEADER_LEN);
+        assert!(t.claim(57).err() == Some(TurbineError::RecordTooLarge { len: 57, max: 56 }));
+        assert!(t.write(&[1; 8]) == Err(TurbineError::NotFinalized));
+
+        let e1 = t.ep_new().unwrap();
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let handle = thread::spawn(move || {
+            let mut records = vec![];
+            event_processor.start::<BusyWait>(|data: &[u8]| -> Result<(), TurbineError> {
+                records.push(data.to_vec());
+                if records.len() == 2 { Err(TurbineError::Shutdown) } else { Ok(()) }
+            });
+            records
+        });
+
+        // 40 bytes, then a record which only fits after 24 bytes of padding, once
+        // the EP has released the first one
+        t.write(&[1; 32]).unwrap();
+        let mut claim = t.claim(20).unwrap();
+        assert!(claim.len() == 20);
+        claim.copy_from_slice(&[2; 20]);
+        claim.commit();
+        assert!(t.turbine.current_pos == 40 + 24 + 32);
+
+        let records = handle.join().unwrap();
+        assert!(records == vec![vec![1; 32], vec![2; 20]]);
+    }
+
+    #[test]
+    fn test_err_stops_after_record() {
+        let mut t: ByteTurbine = ByteTurbine::new(256).unwrap();
+        let e1 = t.ep_new().unwrap();
+        let event_processor = t.ep_finalize(e1).unwrap();
+
+        // Four 16 byte records arrive as a single slice
+        for i in 0..4 {
+            t.write(&[i; 8]).unwrap();
+        }
+
+        let mut seen = vec![];
+        event_processor.start::<BusyWait>(|data: &[u8]| -> Result<(), TurbineError> {
+            seen.push(data[0]);
+            if data[0] == 2 { Err(TurbineError::Shutdown) } else { Ok(()) }
+        });
+
+        // The record after the failing one is still unconsumed
+        assert!(seen == vec![0, 1, 2]);
+        assert!(t.turbine.cursors[e1 + 1].load(Ordering::SeqCst) == 48);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_capacity_fits_header() {
+        let size = u32::MAX as usize + 1;
+        assert!(ByteTurbine::new(size).err() == Some(TurbineError::BufferTooLarge { size, max: u32::MAX as usize }));
+    }
+
+    #[test]
+    fn test_near_maximum_record_wraps() {
+        let mut t: ByteTurbine = ByteTurbine::new(64).unwrap();
+        let e1 = t.ep_new().unwrap();
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let handle = thread::spawn(move || {
+            let mut records = vec![];
+            event_processor.start_with(YieldingWait::new(10), |data: &[u8]| -> Result<(), TurbineError> {
+                records.push(data.to_vec());
+                if records.len() == 4 { Err(TurbineError::Shutdown) } else { Ok(()) }
+            });
+            records
+        });
+
+        // With its header, the 48 byte record takes 56 bytes.  At offset 40 it needs
+        // 24 bytes of padding, and the two together are more than the whole buffer
+        t.write(&[1; 32]).unwrap();
+        t.write(&[2; 48]).unwrap();
+        t.write(&[3; 56]).unwrap();
+        t.write(&[4; 56]).unwrap();
+
+        let records = handle.join().unwrap();
+        assert!(records == vec![vec![1; 32], vec![2; 48], vec![3; 56], vec![4; 56]]);
+    }
+}
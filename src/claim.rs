@@ -0,0 +1,61 @@
+use std::ops::{Deref, DerefMut};
+use crate::Turbine;
+use crate::waitstrategy::{ProducerWaitStrategy, BusyWait};
+
+/// A claimed, unpublished slot in the RingBuffer
+///
+/// A Claim is obtained from `Turbine::claim()` and grants the producer mutable
+/// access to the next free slot in the buffer.  The slot still contains whatever
+/// data was last written to it, so any allocations it owns (e.g. a `Vec<u8>`
+/// buffer) can be cleared and reused instead of building a new Slot per event.
+///
+/// The slot is published to EventProcessors when the Claim is committed or
+/// dropped.  Since a Claim mutably borrows Turbine, only one slot may be claimed
+/// at a time.
+pub struct Claim<'a, T: Send, P: ProducerWaitStrategy = BusyWait> {
+    turbine: &'a mut Turbine<T, P>,
+    slot: *mut T
+}
+
+impl<'a, T: Send, P: ProducerWaitStrategy> Claim<'a, T, P> {
+
+    /// Instantiate a new Claim.
+    ///
+    /// This is for internal use only; `slot` must point at the slot belonging to
+    /// `turbine.current_pos`, which the producer has already verified is free.
+    pub(crate) fn new(turbine: &'a mut Turbine<T, P>, slot: *mut T) -> Claim<'a, T, P> {
+        Claim::<T, P> {
+            turbine,
+            slot
+        }
+    }
+
+    /// Publish the claimed slot, making it visible to EventProcessors.
+    ///
+    /// This is equivalent to dropping the Claim, but makes the intent explicit.
+    pub fn commit(self) {
+        drop(self);
+    }
+}
+
+impl<'a, T: Send, P: ProducerWaitStrategy> Deref for Claim<'a, T, P> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // This is safe because no EventProcessor can read the slot until it has
+        // been published, and the Claim holds the only reference to it
+        unsafe { &*self.slot }
+    }
+}
+
+impl<'a, T: Send, P: ProducerWaitStrategy> DerefMut for Claim<'a, T, P> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.slot }
+    }
+}
+
+impl<'a, T: Send, P: ProducerWaitStrategy> Drop for Claim<'a, T, P> {
+    fn drop(&mut self) {
+        self.turbine.publish();
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use crate::waitstrategy::Notifier;
+use crate::cursors::Cursors;
+use crate::shm::Segment;
+use crate::error::TurbineError;
+
+/// Shared state which tells EventProcessors where the stream ends
+///
+/// While a Turbine is running the end is unbounded.  Halting or shutting down
+/// the Turbine moves the end to a fixed sequence and wakes every blocked
+/// EventProcessor.  An EP stops once it reaches the end, and WaitStrategies stop
+/// waiting for a sequence which lies at or beyond it (see `WaitStrategy::wait_for`).
+///
+/// The end only ever moves backwards, so a halt cannot be undone by a later shutdown.
+///
+/// A Turbine is poisoned when an ExceptionHandler gives up on a panicking EP, or
+/// when an EventHandler fails with `TurbineError::Poisoned`.
+/// Poisoning halts every EP and makes blocking writes fail.
+///
+/// For a shared Turbine, the end and the poisoned flag live in the shared memory
+/// segment, so every attached process stops together.
+pub struct Alert {
+    flags: Flags,
+    root: OnceLock<Arc<Cursors>>,
+    notifier: Arc<Notifier>
+}
+
+impl Alert {
+
+    /// Instantiate a new Alert.
+    ///
+    /// This is for internal use only.  `notifier` is signalled whenever the end moves.
+    pub(crate) fn new(notifier: Arc<Notifier>) -> Alert {
+        Alert {
+            flags: Flags::Local { end: AtomicUsize::new(usize::MAX), poisoned: AtomicBool::new(false) },
+            root: OnceLock::new(),
+            notifier
+        }
+    }
+
+    /// Instantiate a new Alert whose flags live in a shared memory segment.
+    ///
+    /// This is for internal use only.  Only this process's `notifier` is signalled
+    /// when the end moves.
+    pub(crate) fn shared(notifier: Arc<Notifier>, segment: Arc<Segment>) -> Alert {
+        Alert {
+            flags: Flags::Shared(segment),
+            root: OnceLock::new(),
+            notifier
+        }
+    }
+
+    /// Record the cursors once the graph is finalized, so that `shutdown` can find
+    /// the writer's position.
+    pub(crate) fn set_cursors(&self, cursors: Arc<Cursors>) {
+        let _ = self.root.set(cursors);
+    }
+
+    /// True if the stream ends at or before `sequence`, e.g. there is nothing more
+    /// to wait for.
+    pub fn is_ended(&self, sequence: u64) -> bool {
+        sequence >= self.flags.end().load(Ordering::SeqCst) as u64
+    }
+
+    /// The sequence at which the stream ends, or None while it is still running.
+    pub fn end(&self) -> Option<u64> {
+        match self.flags.end().load(Ordering::SeqCst) {
+            usize::MAX => None,
+            end => Some(end as u64)
+        }
+    }
+
+    /// True if the Turbine has been poisoned by a failed EventProcessor.
+    pub fn is_poisoned(&self) -> bool {
+        self.flags.poisoned().load(Ordering::SeqCst)
+    }
+
+    /// Check that events written now would still be processed
+    ///
+    /// Returns `TurbineError::Poisoned` once the Turbine has been poisoned, and
+    /// `TurbineError::Stopped` once it has been halted or shut down.  Either way the
+    /// EPs no longer advance, so a writer waiting for a slot would wait forever.
+    pub(crate) fn check_running(&self) -> Result<(), TurbineError> {
+        // Poisoning sets the flag before the end, so checking the end first never
+        // mistakes a Turbine which is being poisoned for one which was halted
+        match self.end() {
+            None => Ok(()),
+            Some(_) if self.is_poisoned() => Err(TurbineError::Poisoned),
+            Some(_) => Err(TurbineError::Stopped)
+        }
+    }
+
+    /// Mark the Turbine as poisoned and halt every EventProcessor.
+    pub(crate) fn poison(&self) {
+        self.flags.poisoned().store(true, Ordering::SeqCst);
+        self.halt();
+    }
+
+    /// Stop every EventProcessor as soon as its current batch is done.
+    pub(crate) fn halt(&self) {
+        self.end_at(0);
+    }
+
+    /// Stop every EventProcessor once it has processed everything published so far.
+    pub(crate) fn shutdown(&self) {
+        let published = match self.root.get() {
+            Some(cursors) => cursors[0].load(Ordering::SeqCst) as u64,
+            None => 0
+        };
+        self.end_at(published);
+    }
+
+    fn end_at(&self, sequence: u64) {
+        let previous = self.flags.end().fetch_min(sequence as usize, Ordering::SeqCst);
+        debug!("Alert: stream ends at {} (was {})", sequence, previous);
+        self.notifier.notify_all();
+    }
+}
+
+/// Where an Alert keeps the end of the stream and the poisoned flag
+enum Flags {
+    Local { end: AtomicUsize, poisoned: AtomicBool },
+    Shared(Arc<Segment>)
+}
+
+impl Flags {
+
+    fn end(&self) -> &AtomicUsize {
+        match *self {
+            Flags::Local { ref end, .. } => end,
+            Flags::Shared(ref segment) => &segment.header().end
+        }
+    }
+
+    fn poisoned(&self) -> &AtomicBool {
+        match *self {
+            Flags::Local { ref poisoned, .. } => poisoned,
+            Flags::Shared(ref segment) => &segment.header().poisoned
+        }
+    }
+}
+
+/// A cloneable handle which stops the EventProcessors of a Turbine
+///
+/// Obtained from `Turbine::control()`.  Both methods wake any EventProcessor which
+/// is blocked waiting for data, whatever WaitStrategy it uses.
+#[derive(Clone)]
+pub struct Control {
+    alert: Arc<Alert>
+}
+
+impl Control {
+
+    /// Instantiate a new Control handle.
+    ///
+    /// This is for internal use only.
+    pub(crate) fn new(alert: Arc<Alert>) -> Control {
+        Control { alert }
+    }
+
+    /// Stop every EventProcessor as soon as possible.
+    ///
+    /// Waiting EPs stop immediately and busy EPs stop after their current batch,
+    /// so events which have been published may be left unprocessed.  Writes fail
+    /// with `TurbineError::Stopped` from then on.
+    pub fn halt(&self) {
+        self.alert.halt();
+    }
+
+    /// Stop every EventProcessor once everything published so far has been processed.
+    ///
+    /// Events published after this call are not processed, so writes fail with
+    /// `TurbineError::Stopped` from then on.  This is also what happens when the
+    /// Turbine, or the last of its Producers, is dropped.
+    pub fn shutdown(&self) {
+        self.alert.shutdown();
+    }
+
+    /// The sequence at which the stream ends, or None while it is still running.
+    pub fn end(&self) -> Option<u64> {
+        self.alert.end()
+    }
+
+    /// True if a failed EventProcessor has poisoned the Turbine.
+    pub fn is_poisoned(&self) -> bool {
+        self.alert.is_poisoned()
+    }
+}
+
+/// Signals end-of-stream when the writer goes away
+///
+/// Owned by the Turbine, and moved into the Producers' shared state by
+/// `Turbine::into_producer`, so it is dropped along with the last writer.
+pub(crate) struct EndOfStream {
+    alert: Arc<Alert>
+}
+
+impl EndOfStream {
+    pub(crate) fn new(alert: Arc<Alert>) -> EndOfStream {
+        EndOfStream { alert }
+    }
+
+    /// The Alert which is signalled on drop
+    pub(crate) fn alert(&self) -> &Alert {
+        &self.alert
+    }
+}
+
+impl Drop for EndOfStream {
+    fn drop(&mut self) {
+        self.alert.shutdown();
+    }
+}
@@ -0,0 +1,32 @@
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use crate::shm::Segment;
+
+/// The cursors of a finalized graph, the writer's first
+///
+/// They normally live on the heap, but for a shared Turbine they live in its
+/// shared memory segment so that other processes see them.
+pub(crate) enum Cursors {
+    Local(Vec<AtomicUsize>),
+    Shared(Arc<Segment>, usize)
+}
+
+impl Cursors {
+
+    /// Allocate `count` cursors on the heap, all at zero
+    pub(crate) fn local(count: usize) -> Cursors {
+        Cursors::Local((0..count).map(|_| AtomicUsize::new(0)).collect())
+    }
+}
+
+impl Deref for Cursors {
+    type Target = [AtomicUsize];
+
+    fn deref(&self) -> &[AtomicUsize] {
+        match *self {
+            Cursors::Local(ref cursors) => cursors,
+            Cursors::Shared(ref segment, count) => &segment.cursors()[..count]
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::Turbine;
+use crate::eventhandler::EventHandler;
+use crate::eventprocessor::EventProcessor;
+use crate::error::TurbineError;
+use crate::exceptionhandler::{ExceptionHandler, BoxedExceptionHandler};
+use crate::waitstrategy::{WaitStrategy, ProducerWaitStrategy, BusyWait};
+
+/// A boxed EventHandler, as stored by the pipeline builder
+pub type BoxedHandler<T> = Box<dyn EventHandler<T> + Send>;
+
+/// Drives a handler with the WaitStrategy configured for its processor
+type Runner<T> = fn(EventProcessor<T>, BoxedHandler<T>) -> BoxedHandler<T>;
+
+fn run_handler<T: Send, W: WaitStrategy>(event_processor: EventProcessor<T>,
+                                         handler: BoxedHandler<T>) -> BoxedHandler<T> {
+    event_processor.run::<W, _>(handler)
+}
+
+/// A handler added through the pipeline builder, waiting for the graph to be finalized
+pub(crate) struct PendingHandler<T> {
+    pub(crate) processor: Processor,
+    pub(crate) handler: BoxedHandler<T>,
+    runner: Runner<T>,
+    exception_handler: Option<BoxedExceptionHandler<T>>
+}
+
+impl<T: Send> PendingHandler<T> {
+
+    /// A handler which busy-waits unless configured otherwise
+    pub(crate) fn new(processor: Processor, handler: BoxedHandler<T>) -> PendingHandler<T> {
+        PendingHandler { processor, handler, runner: run_handler::<T, BusyWait>, exception_handler: None }
+    }
+
+    /// Pair the handler with its finalized EventProcessor
+    pub(crate) fn bind(self, event_processor: EventProcessor<T>) -> BoundProcessor<T> {
+        let event_processor = match self.exception_handler {
+            Some(exception_handler) => event_processor.with_boxed_exception_handler(exception_handler),
+            None => event_processor
+        };
+        BoundProcessor {
+            processor: self.processor,
+            event_processor,
+            handler: self.handler,
+            runner: self.runner
+        }
+    }
+}
+
+/// A typed handle to an EventProcessor added through the pipeline builder
+///
+/// Handles are returned by `HandlerGroup::processors` and can be passed to
+/// `Turbine::after` to make later stages depend on them.  `token()` exposes the
+/// underlying index for use with `ep_depends` and `ep_finalize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Processor(usize);
+
+impl Processor {
+
+    /// Instantiate a new Processor handle.
+    ///
+    /// This is for internal use only.
+    pub(crate) fn new(token: usize) -> Processor {
+        Processor(token)
+    }
+
+    /// The EventProcessor token, as returned by `Turbine::ep_new`.
+    pub fn token(&self) -> usize {
+        self.0
+    }
+}
+
+/// A set of EventHandlers which form one stage of a pipeline
+///
+/// Implemented for tuples of up to eight EventHandlers, e.g. `(journal, replicate)`,
+/// and for a Vec of boxed handlers when the number of handlers is only known at
+/// runtime.
+pub trait Handlers<T> {
+
+    /// Box each of the handlers
+    fn into_handlers(self) -> Vec<BoxedHandler<T>>;
+}
+
+impl<T> Handlers<T> for Vec<BoxedHandler<T>> {
+    fn into_handlers(self) -> Vec<BoxedHandler<T>> {
+        self
+    }
+}
+
+macro_rules! impl_handlers(
+    ($($name:ident),+) => (
+        impl<T, $($name: EventHandler<T> + Send + 'static),+> Handlers<T> for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_handlers(self) -> Vec<BoxedHandler<T>> {
+                let ($($name,)+) = self;
+                vec![$(Box::new($name)),+]
+            }
+        }
+    );
+);
+
+impl_handlers!(A);
+impl_handlers!(A, B);
+impl_handlers!(A, B, C);
+impl_handlers!(A, B, C, D);
+impl_handlers!(A, B, C, D, E);
+impl_handlers!(A, B, C, D, E, F);
+impl_handlers!(A, B, C, D, E, F, G);
+impl_handlers!(A, B, C, D, E, F, G, H);
+
+/// A group of EventProcessors in a pipeline under construction
+///
+/// Obtained from `Turbine::handle_events_with` or `Turbine::after`.  Every
+/// handler added with `then` or `then_all` depends on *all* the processors in
+/// the group, so fanning out and joining back in builds a diamond.
+pub struct HandlerGroup<'a, T, P = BusyWait> {
+    turbine: &'a mut Turbine<T, P>,
+    processors: Vec<Processor>
+}
+
+impl<'a, T: Send + 'static, P: ProducerWaitStrategy> HandlerGroup<'a, T, P> {
+
+    /// Instantiate a new HandlerGroup.
+    ///
+    /// This is for internal use only.
+    pub(crate) fn new(turbine: &'a mut Turbine<T, P>, processors: Vec<Processor>) -> HandlerGroup<'a, T, P> {
+        HandlerGroup { turbine, processors }
+    }
+
+    /// Add a single handler which depends on every processor in this group.
+    ///
+    /// Returns a group containing only the new processor.  Fails with
+    /// `TurbineError::TooManyProcessors` if the Turbine is shared and its segment has
+    /// no room for another EventProcessor.
+    pub fn then<H>(self, handler: H) -> Result<HandlerGroup<'a, T, P>, TurbineError>
+        where H: EventHandler<T> + Send + 'static {
+        self.then_all((handler,))
+    }
+
+    /// Add several handlers, each of which depends on every processor in this group.
+    ///
+    /// The new handlers run in parallel with each other.  Returns a group containing
+    /// only the new processors, or the same errors as `then`.
+    pub fn then_all<H: Handlers<T>>(self, handlers: H) -> Result<HandlerGroup<'a, T, P>, TurbineError> {
+        let processors = handlers.into_handlers().into_iter()
+            .map(|handler| self.turbine.add_handler(handler, &self.processors))
+            .collect::<Result<_, _>>()?;
+
+        Ok(HandlerGroup { turbine: self.turbine, processors })
+    }
+
+    /// Use WaitStrategy `W`, with its default configuration, for every processor in this group.
+    ///
+    /// This is the strategy used by `BoundProcessor::start` and `Turbine::start_all`.
+    /// Processors busy-wait unless configured otherwise.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///# use turbine::{Turbine, Slot, EventHandler, BlockingWait, TurbineError};
+    ///# struct TestSlot;
+    ///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot } }
+    ///# struct Stage;
+    ///# impl EventHandler<TestSlot> for Stage {
+    ///#     fn on_event(&mut self, _: &TestSlot, _: u64, _: bool) -> Result<(), TurbineError> { Ok(()) }
+    ///# }
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+    ///t.handle_events_with((Stage, Stage)).unwrap()
+    /// .with_wait_strategy::<BlockingWait>();
+    ///```
+    pub fn with_wait_strategy<W: WaitStrategy>(self) -> HandlerGroup<'a, T, P> {
+        for pending in self.turbine.handlers.iter_mut() {
+            if self.processors.contains(&pending.processor) {
+                pending.runner = run_handler::<T, W>;
+            }
+        }
+        self
+    }
+
+    /// Give every processor in this group its own clone of `handler`, to decide
+    /// what happens when its EventHandler panics.  See `ExceptionHandler`.
+    pub fn with_exception_handler<E>(self, handler: E) -> HandlerGroup<'a, T, P>
+        where E: ExceptionHandler<T> + Clone + Send + 'static {
+        for pending in self.turbine.handlers.iter_mut() {
+            if self.processors.contains(&pending.processor) {
+                pending.exception_handler = Some(Box::new(handler.clone()));
+            }
+        }
+        self
+    }
+
+    /// Handles to the processors in this group
+    pub fn processors(&self) -> &[Processor] {
+        &self.processors
+    }
+}
+
+/// A finalized EventProcessor paired with the EventHandler it was configured with
+///
+/// Returned by `Turbine::finalize_handlers`.  Each BoundProcessor is usually moved
+/// to its own thread and started with `run`.
+pub struct BoundProcessor<T> {
+    processor: Processor,
+    event_processor: EventProcessor<T>,
+    handler: BoxedHandler<T>,
+    runner: Runner<T>
+}
+
+impl<T: Send> BoundProcessor<T> {
+
+    /// The handle of this processor
+    pub fn processor(&self) -> Processor {
+        self.processor
+    }
+
+    /// Drive the handler with the WaitStrategy configured through
+    /// `HandlerGroup::with_wait_strategy` (busy-waiting by default).
+    ///
+    /// The handler is returned once the EventProcessor shuts down.
+    pub fn start(self) -> BoxedHandler<T> {
+        (self.runner)(self.event_processor, self.handler)
+    }
+
+    /// Drive the handler with WaitStrategy `W`, see `EventProcessor::run`.
+    ///
+    /// The handler is returned once the EventProcessor shuts down.
+    pub fn run<W: WaitStrategy>(self) -> BoxedHandler<T> {
+        self.event_processor.run::<W, _>(self.handler)
+    }
+
+    /// Drive the handler with a pre-configured WaitStrategy, see `EventProcessor::run_with`.
+    pub fn run_with<W: WaitStrategy>(self, wait_strategy: W) -> BoxedHandler<T> {
+        self.event_processor.run_with(wait_strategy, self.handler)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{Turbine, Slot, EventHandler, BoundProcessor, YieldingWait, TurbineError};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::thread;
+
+    struct TestSlot {
+        pub value: u64
+    }
+
+    impl Slot for TestSlot {
+        fn new() -> TestSlot {
+            TestSlot { value: 0 }
+        }
+    }
+
+    /// Records its own progress, and checks that its upstream stages always
+    /// finished an event first
+    struct StageHandler {
+        id: usize,
+        upstream: Vec<usize>,
+        progress: Arc<Vec<AtomicU64>>,
+        count: u64
+    }
+
+    impl StageHandler {
+        fn new(id: usize, upstream: &[usize], progress: &Arc<Vec<AtomicU64>>, count: u64) -> StageHandler {
+            StageHandler { id, upstream: upstream.to_vec(), progress: progress.clone(), count }
+        }
+    }
+
+    impl EventHandler<TestSlot> for StageHandler {
+        fn on_event(&mut self, event: &TestSlot, sequence: u64, _: bool) -> Result<(), TurbineError> {
+            assert!(event.value == sequence);
+            for &up in self.upstream.iter() {
+                assert!(self.progress[up].load(Ordering::SeqCst) > sequence);
+            }
+            self.progress[self.id].store(sequence + 1, Ordering::SeqCst);
+
+            if sequence + 1 >= self.count { Err(TurbineError::Shutdown) } else { Ok(()) }
+        }
+    }
+
+    fn run_all(mut t: Turbine<TestSlot>, processors: Vec<BoundProcessor<TestSlot>>,
+               progress: &Arc<Vec<AtomicU64>>, count: u64) {
+        let handles: Vec<_> = processors.into_iter()
+            .map(|p| thread::spawn(move || { p.run_with(YieldingWait::new(10)); }))
+            .collect();
+
+        for i in 0..count {
+            t.write(TestSlot { value: i }).unwrap();
+        }
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(progress.iter().all(|p| p.load(Ordering::SeqCst) == count));
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let count = 1000;
+        let progress = Arc::new((0..5).map(|_| AtomicU64::new(0)).collect::<Vec<_>>());
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+
+        // 0, 1 --> 2 --> 3, 4
+        let tail = t.handle_events_with((StageHandler::new(0, &[], &progress, count),
+                                         StageHandler::new(1, &[], &progress, count))).unwrap()
+            .then(StageHandler::new(2, &[0, 1], &progress, count)).unwrap()
+            .then_all((StageHandler::new(3, &[2], &progress, count),
+                       StageHandler::new(4, &[2], &progress, count))).unwrap();
+        assert!(tail.processors().len() == 2);
+
+        let processors = t.finalize_handlers().unwrap();
+        assert!(processors.len() == 5);
+        let late = t.handle_events_with((StageHandler::new(0, &[], &progress, count),));
+        assert!(late.err() == Some(TurbineError::AlreadyFinalized));
+
+        run_all(t, processors, &progress, count);
+    }
+
+    #[test]
+    fn test_pipeline_after() {
+        let count = 1000;
+        let progress = Arc::new((0..4).map(|_| AtomicU64::new(0)).collect::<Vec<_>>());
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+
+        // 0 --> 1, 0 --> 2, and 3 joins the two branches
+        let stage = |id, upstream: &[usize]| StageHandler::new(id, upstream, &progress, count);
+        let first = t.handle_events_with((stage(0, &[]),)).unwrap().processors().to_vec();
+        let left = t.after(&first).unwrap().then(stage(1, &[0])).unwrap().processors().to_vec();
+        let right = t.after(&first).unwrap().then(stage(2, &[0])).unwrap().processors().to_vec();
+
+        let joined: Vec<_> = left.iter().chain(right.iter()).cloned().collect();
+        t.after(&joined).unwrap().then(stage(3, &[1, 2])).unwrap();
+
+        let processors = t.finalize_handlers().unwrap();
+        let tokens: Vec<usize> = processors.iter().map(|p| p.processor().token()).collect();
+        assert!(tokens == vec![0, 1, 2, 3]);
+
+        run_all(t, processors, &progress, count);
+    }
+
+    #[test]
+    fn test_pipeline_too_many_processors() {
+        struct Noop;
+
+        impl EventHandler<u64> for Noop {
+            fn on_event(&mut self, _: &u64, _: u64, _: bool) -> Result<(), TurbineError> {
+                Ok(())
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("turbine-dsl-{}", std::process::id()));
+        let mut t: Turbine<u64> = unsafe { Turbine::create_shared(&path, 64, 2) }.unwrap();
+
+        // The segment only has room for two processors, which is an error rather than a panic
+        let group = t.handle_events_with((Noop, Noop)).unwrap();
+        assert!(group.then(Noop).err() == Some(TurbineError::TooManyProcessors(2)));
+        assert!(t.handle_events_with((Noop,)).err() == Some(TurbineError::TooManyProcessors(2)));
+
+        assert!(t.finalize_handlers().unwrap().len() == 2);
+        drop(t);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The error returned when `try_write` or `write_timeout` could not write data
+///
+/// `error` says why: `TurbineError::Full` if there was no free slot in time, or
+/// `TurbineError::Poisoned` if nothing written would be processed.  The rejected
+/// data is handed back so that it can be retried later without being rebuilt.
+pub struct WriteError<T> {
+    data: T,
+    error: TurbineError
+}
+
+impl<T> WriteError<T> {
+
+    pub(crate) fn new(data: T, error: TurbineError) -> WriteError<T> {
+        WriteError { data, error }
+    }
+
+    /// Why the data could not be written.
+    pub fn error(&self) -> &TurbineError {
+        &self.error
+    }
+
+    /// Recover the data which could not be written.
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+impl<T> fmt::Debug for WriteError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WriteError({:?}, ..)", self.error)
+    }
+}
+
+impl<T> fmt::Display for WriteError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl<T> Error for WriteError<T> {}
+
+/// The error returned when a wait timed out before any data became available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("timed out waiting for data")
+    }
+}
+
+impl Error for Timeout {}
+
+/// The errors returned across the Turbine API
+///
+/// Besides describing invalid sizes and dependency graphs, TurbineError is what
+/// EventProcessor closures and EventHandlers return to stop processing, usually
+/// `TurbineError::Shutdown`.  The more specific `WriteError` and `Timeout` errors convert
+/// into it, so `?` can be used inside a closure.
+///
+/// New variants may be added in later releases, so matches on it need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TurbineError {
+    /// The dependency graph has been finalized, so it can no longer be changed
+    AlreadyFinalized,
+
+    /// The ring buffer size is zero, or is not a power of two where one is required
+    InvalidSize(usize),
+
+    /// A ByteTurbine buffer of `size` bytes is smaller than the `min` bytes needed to
+    /// hold a record
+    BufferTooSmall { size: usize, min: usize },
+
+    /// A ByteTurbine buffer of `size` bytes is larger than the `max` bytes whose
+    /// record lengths can be encoded
+    BufferTooLarge { size: usize, max: usize },
+
+    /// The token was never handed out by `ep_new`
+    UnknownProcessor(usize),
+
+    /// `processor` depends on a token which was never handed out by `ep_new`,
+    /// so it would wait forever on a cursor nobody advances
+    UnknownDependency { processor: usize, dependency: usize },
+
+    /// The EventProcessor depends on itself
+    SelfDependency(usize),
+
+    /// The dependencies form a cycle.  The path starts and ends with the same token,
+    /// and each token depends on the one after it.
+    Cycle(Vec<usize>),
+
+    /// A mutable EventProcessor could run on the same sequence as `concurrent`
+    Unordered { processor: usize, concurrent: usize },
+
+    /// The token was handed out by `ep_new` but never finalized, so nothing will
+    /// advance its cursor and the writer would wait forever once the ring fills
+    Orphaned(usize),
+
+    /// The token has already been finalized in a way which can't be shared, e.g. into
+    /// a mutable EventProcessor or a WorkerPool
+    AlreadyBound(usize),
+
+    /// The ring buffer is full
+    Full,
+
+    /// A wait timed out before any data became available
+    Timeout,
+
+    /// The EventProcessor should stop processing
+    Shutdown,
+
+    /// An ExceptionHandler poisoned the Turbine after an EventProcessor panicked, or
+    /// an EventHandler failed for good (e.g. a Journal's I/O error), so nothing written
+    /// to it will be processed
+    Poisoned,
+
+    /// The Turbine has been halted or shut down, so nothing written to it will be processed
+    Stopped,
+
+    /// A ByteTurbine record of `len` bytes is longer than the largest record
+    /// which fits in its buffer, or a journaled event encodes to more bytes than
+    /// a journal record can hold
+    RecordTooLarge { len: usize, max: usize },
+
+    /// The graph already has as many EventProcessors as its shared memory segment
+    /// has room for
+    TooManyProcessors(usize),
+
+    /// An I/O error on a shared memory segment or a journal.  `message` is the
+    /// operating system's description, prefixed with the file involved where known.
+    Io { kind: io::ErrorKind, message: String },
+
+    /// The file is not a shared memory segment, or was created for a different Slot type
+    IncompatibleSegment,
+
+    /// The dependency graph has not been finalized yet, or has no EventProcessors, so
+    /// there is nothing to write to, or for a shared memory segment to attach to
+    NotFinalized,
+
+    /// A journal record is damaged, or out of order, at `sequence`
+    CorruptJournal(u64),
+
+    /// The journal holds no events from `sequence` onwards, e.g. because its older
+    /// segments have been removed
+    NotJournaled(u64),
+
+    /// A Journal was handed `sequence` when the next sequence it expected was
+    /// `expected`, so the events in between would be missing from it
+    JournalGap { expected: u64, sequence: u64 },
+
+    /// A Journal which already holds every sequence before `next` was handed
+    /// `sequence`, which is not being replayed, e.g. because the Turbine was restarted
+    /// without `Turbine::recover`.  Skipping it would lose the event.
+    JournalStale { next: u64, sequence: u64 }
+}
+
+impl fmt::Display for TurbineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TurbineError::AlreadyFinalized => f.write_str("the dependency graph has already been finalized"),
+            TurbineError::InvalidSize(0) => f.write_str("buffer size must be greater than zero"),
+            TurbineError::InvalidSize(size) => write!(f, "buffer size {} is not a power of two", size),
+            TurbineError::BufferTooSmall { size, min } =>
+                write!(f, "buffer of {} bytes is smaller than the minimum of {} bytes", size, min),
+            TurbineError::BufferTooLarge { size, max } =>
+                write!(f, "buffer of {} bytes is larger than the maximum of {} bytes", size, max),
+            TurbineError::UnknownProcessor(token) => write!(f, "unknown event processor {}", token),
+            TurbineError::UnknownDependency { processor, dependency } =>
+                write!(f, "event processor {} depends on unknown event processor {}", processor, dependency),
+            TurbineError::SelfDependency(token) => write!(f, "event processor {} depends on itself", token),
+            TurbineError::Cycle(ref path) => {
+                let path: Vec<String> = path.iter().map(|t| t.to_string()).collect();
+                write!(f, "dependency cycle between event processors: {}", path.join(" -> "))
+            },
+            TurbineError::Unordered { processor, concurrent } =>
+                write!(f, "mutable event processor {} is not ordered against event processor {}", processor, concurrent),
+            TurbineError::Orphaned(token) =>
+                write!(f, "event processor {} was never finalized, so the ring would never drain", token),
+            TurbineError::AlreadyBound(token) => write!(f, "event processor {} has already been finalized", token),
+            TurbineError::Full => f.write_str("ring buffer is full"),
+            TurbineError::Timeout => f.write_str("timed out waiting for data"),
+            TurbineError::Shutdown => f.write_str("event processor shut down"),
+            TurbineError::Poisoned => f.write_str("turbine was poisoned by a failed event processor"),
+            TurbineError::Stopped => f.write_str("turbine was halted or shut down"),
+            TurbineError::RecordTooLarge { len, max } =>
+                write!(f, "record of {} bytes is larger than the maximum of {} bytes", len, max),
+            TurbineError::TooManyProcessors(max) => write!(f, "the shared memory segment only has room for {} event processors", max),
+            TurbineError::Io { ref message, .. } => write!(f, "I/O error: {}", message),
+            TurbineError::IncompatibleSegment => f.write_str("file is not a shared memory segment for this slot type"),
+            TurbineError::NotFinalized => f.write_str("the dependency graph has not been finalized"),
+            TurbineError::CorruptJournal(sequence) => write!(f, "journal is corrupt at sequence {}", sequence),
+            TurbineError::NotJournaled(sequence) => write!(f, "journal does not contain sequence {}", sequence),
+            TurbineError::JournalGap { expected, sequence } =>
+                write!(f, "journal expected sequence {} but was given {}", expected, sequence),
+            TurbineError::JournalStale { next, sequence } =>
+                write!(f, "journal already holds sequence {}, which is not being replayed (next is {})", sequence, next)
+        }
+    }
+}
+
+impl Error for TurbineError {}
+
+impl<T> From<WriteError<T>> for TurbineError {
+    fn from(e: WriteError<T>) -> TurbineError {
+        e.error
+    }
+}
+
+impl From<io::Error> for TurbineError {
+    fn from(e: io::Error) -> TurbineError {
+        TurbineError::Io { kind: e.kind(), message: e.to_string() }
+    }
+}
+
+impl TurbineError {
+
+    /// Convert an I/O error on the file or directory at `path`, keeping the path in the message
+    pub(crate) fn io_at(path: &Path, e: io::Error) -> TurbineError {
+        TurbineError::Io { kind: e.kind(), message: format!("{}: {}", path.display(), e) }
+    }
+}
+
+impl From<Timeout> for TurbineError {
+    fn from(_: Timeout) -> TurbineError {
+        TurbineError::Timeout
+    }
+}
@@ -0,0 +1,108 @@
+use crate::error::TurbineError;
+use std::time::Duration;
+
+/// A stateful consumer of events, driven by `EventProcessor::run`
+///
+/// EventHandler is an alternative to passing a closure to `EventProcessor::start`.
+/// Rather than receiving raw slices of the ring buffer, the handler is called once
+/// per event along with the event's absolute sequence and whether it is the last
+/// event in the currently available batch.  Since handlers are plain structs, they
+/// can be unit-tested by calling these methods directly.
+///
+/// Only `on_event` must be implemented; the lifecycle hooks default to doing nothing.
+///
+///## Example
+///
+///```
+///# use turbine::{Turbine, Slot, EventHandler, BusyWait, TurbineError};
+///# use std::thread;
+///# struct TestSlot { value: isize }
+///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+///struct Summer {
+///    total: isize
+///}
+///
+///impl EventHandler<TestSlot> for Summer {
+///    fn on_event(&mut self, event: &TestSlot, sequence: u64, end_of_batch: bool) -> Result<(), TurbineError> {
+///        self.total += event.value;
+///        if sequence == 9 { Err(TurbineError::Shutdown) } else { Ok(()) }
+///    }
+///}
+///
+///let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+///let e1 = t.ep_new().unwrap();
+///let event_processor = t.ep_finalize(e1).unwrap();
+///
+///let handle = thread::spawn(move || {
+///    event_processor.run::<BusyWait, _>(Summer { total: 0 })
+///});
+///
+///for i in 0..10 {
+///    t.write(TestSlot { value: i }).unwrap();
+///}
+///assert!(handle.join().unwrap().total == 45);
+///```
+pub trait EventHandler<T> {
+
+    /// Process a single event
+    ///
+    /// `sequence` is the absolute position of the event in the ring buffer, and
+    /// `end_of_batch` is true for the last event currently available to this EP.
+    /// Returning an Err (usually `TurbineError::Shutdown`) shuts down the EventProcessor
+    /// right after this event.  The rest of the batch is left unprocessed, so EPs which
+    /// depend on this one never see it either.  Returning `TurbineError::Poisoned`
+    /// poisons the Turbine as well, for failures which mean no event after this one
+    /// can be processed, so that writers fail rather than wait for this EP forever.
+    fn on_event(&mut self, event: &T, sequence: u64, end_of_batch: bool) -> Result<(), TurbineError>;
+
+    /// Called once on the EventProcessor's thread, before any events are processed
+    fn on_start(&mut self) {}
+
+    /// Called once on the EventProcessor's thread, after the last event has been processed
+    fn on_shutdown(&mut self) {}
+
+    /// Called before each batch of events is delivered, with the number of events in the batch
+    fn on_batch_start(&mut self, _batch_size: u64) {}
+
+    /// How long the EventProcessor may wait for data before calling `on_timeout`
+    ///
+    /// Defaults to None, e.g. wait forever.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called when no data arrived within `timeout()`
+    ///
+    /// `sequence` is the sequence the EP is waiting for.  Returning an Err shuts down
+    /// the EventProcessor, and `TurbineError::Poisoned` poisons the Turbine as for
+    /// `on_event`.
+    fn on_timeout(&mut self, _sequence: u64) -> Result<(), TurbineError> {
+        Ok(())
+    }
+}
+
+impl<T, H: EventHandler<T> + ?Sized> EventHandler<T> for Box<H> {
+    fn on_event(&mut self, event: &T, sequence: u64, end_of_batch: bool) -> Result<(), TurbineError> {
+        (**self).on_event(event, sequence, end_of_batch)
+    }
+
+    fn on_start(&mut self) {
+        (**self).on_start()
+    }
+
+    fn on_shutdown(&mut self) {
+        (**self).on_shutdown()
+    }
+
+    fn on_batch_start(&mut self, batch_size: u64) {
+        (**self).on_batch_start(batch_size)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        (**self).timeout()
+    }
+
+    fn on_timeout(&mut self, sequence: u64) -> Result<(), TurbineError> {
+        (**self).on_timeout(sequence)
+    }
+}
@@ -1,18 +1,32 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicUsize,Ordering};
+use std::path::Path;
 use std::sync::Arc;
-use waitstrategy::{WaitStrategy};
-use ringbuffer::{RingBuffer, Slot};
+use std::time::Duration;
+use crate::waitstrategy::{WaitStrategy, Notifier};
+use crate::control::Alert;
+use crate::cursors::Cursors;
+use crate::eventhandler::EventHandler;
+use crate::exceptionhandler::{ExceptionHandler, BoxedExceptionHandler};
+use crate::error::TurbineError;
+use crate::ringbuffer::RingBuffer;
+use crate::shm::Segment;
 
 /// EventProcessors provide functionality to process and consume data from the ring buffer
 pub struct EventProcessor<T> {
     graph: Arc<Vec<Vec<usize>>>,
-    cursors: Arc<Vec<AtomicUsize>>,
+    cursors: Arc<Cursors>,
     token: usize,
-    ring: Arc<RingBuffer<T>>
+    ring: Arc<RingBuffer<T>>,
+    notifier: Arc<Notifier>,
+    alert: Arc<Alert>,
+    exception_handler: RefCell<Option<BoxedExceptionHandler<T>>>
 }
 
 
-impl<T: Slot> EventProcessor<T> {
+impl<T: Send> EventProcessor<T> {
 
     /// Instantiate a new EventProcessor.
     ///
@@ -20,16 +34,74 @@ impl<T: Slot> EventProcessor<T> {
     /// - ring: an instance of the ring buffer
     /// - graph: a dependency graph, showing how all the EPs relate to eachother.
     /// - cursors: a vector of AtomicUsize atomics which act as cursors into the ring buffer
+    /// - notifier: signalled whenever a cursor advances, to wake blocked EPs
+    /// - alert: tells the EP where the stream ends once the Turbine is halted or shut down
     /// - token: the index in the graph which represents this EP
-    pub fn new(ring: Arc<RingBuffer<T>>, graph: Arc<Vec<Vec<usize>>>, cursors: Arc<Vec<AtomicUsize>>, token: usize) -> EventProcessor<T> {
+    pub(crate) fn new(ring: Arc<RingBuffer<T>>, graph: Arc<Vec<Vec<usize>>>, cursors: Arc<Cursors>, notifier: Arc<Notifier>, alert: Arc<Alert>, token: usize) -> EventProcessor<T> {
         EventProcessor::<T> {
-            graph: graph,
-            cursors: cursors,
-            token: token,
-            ring: ring
+            graph,
+            cursors,
+            token,
+            ring,
+            notifier,
+            alert,
+            exception_handler: RefCell::new(None)
         }
     }
 
+    /// Attach to the EventProcessor at `token` of a shared Turbine, usually from
+    /// another process.
+    ///
+    /// `path` is the file the Turbine was created with, see `Turbine::create_shared`.
+    /// The EP behaves exactly as one returned by `ep_finalize`, and picks up from its
+    /// cursor, so an EP which is attached again after its process exited resumes
+    /// where it stopped.  Only one EP should be running per token at a time.
+    ///
+    /// The attached EP has a Notifier of its own, which the writer and the EPs of other
+    /// processes never signal, so it must use a polling WaitStrategy such as `BusyWait`,
+    /// `YieldingWait` or `SleepingWait` rather than `BlockingWait`.  The same goes for
+    /// the creating process's EPs; see `Turbine::create_shared`.
+    ///
+    /// Returns `TurbineError::NotFinalized` if the graph has not been finalized yet,
+    /// `TurbineError::IncompatibleSegment` if the file was not created for slots of the
+    /// size and alignment of `T`, and `TurbineError::UnknownProcessor` if `token` is
+    /// not in the graph.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type the Turbine was created with, and must be plain data as
+    /// described by `RingBuffer::create_shared`.  Only its size and alignment can be
+    /// checked, so attaching e.g. as `char` to a ring of `u32` is undefined behaviour.
+    pub unsafe fn attach(path: impl AsRef<Path>, token: usize) -> Result<EventProcessor<T>, TurbineError> where T: Copy {
+        let segment = Arc::new(Segment::attach::<T>(path.as_ref())?);
+        let graph = segment.graph();
+        if token >= graph.len() {
+            return Err(TurbineError::UnknownProcessor(token));
+        }
+
+        let cursors = Arc::new(Cursors::Shared(segment.clone(), graph.len() + 1));
+        let notifier = Arc::new(Notifier::new());
+        let alert = Arc::new(Alert::shared(notifier.clone(), segment.clone()));
+        alert.set_cursors(cursors.clone());
+
+        Ok(EventProcessor::new(Arc::new(RingBuffer::shared(segment)), Arc::new(graph), cursors, notifier, alert, token))
+    }
+
+    /// Catch panics from the closure or handler and pass them to `handler`.
+    ///
+    /// Once a handler is installed, closures passed to `start` and its variants are
+    /// called with one event at a time, so that a panic can be reported along with the
+    /// event which caused it.  See `ExceptionHandler` for the actions it can take.
+    pub fn with_exception_handler(self, handler: impl ExceptionHandler<T> + Send + 'static) -> EventProcessor<T> {
+        self.with_boxed_exception_handler(Box::new(handler))
+    }
+
+    /// Like `with_exception_handler`, for a handler which is already boxed.
+    pub fn with_boxed_exception_handler(self, handler: BoxedExceptionHandler<T>) -> EventProcessor<T> {
+        *self.exception_handler.borrow_mut() = Some(handler);
+        self
+    }
+
     /// Begin waiting for data to arrive from the ring buffer.
     ///
     /// This method is the only "public" method in EventProcessor.rs.
@@ -41,46 +113,338 @@ impl<T: Slot> EventProcessor<T> {
     /// The user-code running inside the closure must be capable of handling multiple pieces of data.
     ///
     /// Upon completion of processing the data, the closure must return a Result signaling if it wants the event processor
-    /// to continue running, or exit.  A Result of Ok(()) will tell the EP to continue running.  A Result of Err(()) will
-    /// shut down the EP.
+    /// to continue running, or exit.  A Result of Ok(()) will tell the EP to continue running.  Any Err will shut down the
+    /// EP; by convention the closure returns Err(TurbineError::Shutdown).
     ///
     /// ## Example
     ///
     ///```
-    ///spawn(proc() {
-    ///     event_processor.start::<BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+    ///# use turbine::{Turbine, Slot, BusyWait, TurbineError};
+    ///# use std::thread;
+    ///# struct TestSlot { value: isize }
+    ///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+    ///# let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+    ///# let e1 = t.ep_new().unwrap();
+    ///# let event_processor = t.ep_finalize(e1).unwrap();
+    ///let handle = thread::spawn(move || {
+    ///     event_processor.start::<BusyWait>(|data: &[TestSlot]| -> Result<(), TurbineError> {
     ///         assert!(data.len() == 1);
     ///         assert!(data[0].value == 19);
-    ///         return Ok(());
+    ///         Err(TurbineError::Shutdown)
     ///     });
     ///});
+    ///# t.write(TestSlot { value: 19 }).unwrap();
+    ///# handle.join().unwrap();
     ///```
-    pub fn start<W: WaitStrategy, F>(&self, f: F) where F: FnMut(&[T]) -> Result<(),()> {
-        let capacity = self.ring.get_capacity();
+    pub fn start<W: WaitStrategy>(&self, f: impl FnMut(&[T]) -> Result<(), TurbineError>) {
+        self.start_with(self.wait_strategy::<W>(), f)
+    }
+
+    /// Instantiate WaitStrategy `W` for this EventProcessor with its default configuration.
+    ///
+    /// The returned strategy may be tuned before being handed to `start_with`.
+    pub fn wait_strategy<W: WaitStrategy>(&self) -> W {
+        WaitStrategy::new(self.ring.get_capacity(), self.notifier.clone())
+    }
+
+    /// Begin waiting for data to arrive from the ring buffer, using a pre-configured WaitStrategy.
+    ///
+    /// This behaves exactly like `start`, but rather than instantiating the WaitStrategy
+    /// with its defaults, the supplied instance is used.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///# use turbine::{Turbine, Slot, PhasedBackoffWait, TurbineError};
+    ///# use std::thread;
+    ///# struct TestSlot { value: isize }
+    ///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+    ///# let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+    ///# let e1 = t.ep_new().unwrap();
+    ///# let event_processor = t.ep_finalize(e1).unwrap();
+    ///let handle = thread::spawn(move || {
+    ///     let wait = event_processor.wait_strategy::<PhasedBackoffWait>().with_tries(1000, 10);
+    ///     event_processor.start_with(wait, |data: &[TestSlot]| -> Result<(), TurbineError> {
+    ///         assert!(data[0].value == 19);
+    ///         Err(TurbineError::Shutdown)
+    ///     });
+    ///});
+    ///# t.write(TestSlot { value: 19 }).unwrap();
+    ///# handle.join().unwrap();
+    ///```
+    pub fn start_with<W: WaitStrategy>(&self, wait_strategy: W, mut f: impl FnMut(&[T]) -> Result<(), TurbineError>) {
+        self.run_loop(wait_strategy, None, |data, sequence, _| self.guard_each(data, sequence, |data, _| f(data)), |_| Ok(()))
+    }
+
+    /// Begin waiting for data to arrive from the ring buffer, waking up if none arrives within `timeout`.
+    ///
+    /// This behaves like `start_with`, but whenever the WaitStrategy waits longer than `timeout`
+    /// without new data becoming available, `on_timeout` is called instead.  This allows
+    /// handlers to flush batched output or emit heartbeats during quiet periods.
+    ///
+    /// `on_timeout` receives the sequence the EP is waiting for (e.g. one past the last
+    /// sequence it processed).  Like the data closure, it returns a Result signaling if the
+    /// EP should continue running (Ok(())) or shut down (Err(TurbineError::Shutdown)).
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///# use turbine::{Turbine, Slot, BlockingWait, TurbineError};
+    ///# use std::thread;
+    ///# use std::time::Duration;
+    ///# struct TestSlot { value: isize }
+    ///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+    ///# let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+    ///# let e1 = t.ep_new().unwrap();
+    ///# let event_processor = t.ep_finalize(e1).unwrap();
+    ///let handle = thread::spawn(move || {
+    ///     let mut heartbeats = 0;
+    ///     let wait = event_processor.wait_strategy::<BlockingWait>();
+    ///     event_processor.start_with_timeout(wait, Duration::from_millis(1),
+    ///         |data: &[TestSlot]| -> Result<(), TurbineError> {
+    ///             // ... process work here ... //
+    ///             Ok(())
+    ///         },
+    ///         |sequence: u64| -> Result<(), TurbineError> {
+    ///             // Nothing arrived for 1ms, stop after three heartbeats
+    ///             heartbeats += 1;
+    ///             if heartbeats == 3 { Err(TurbineError::Shutdown) } else { Ok(()) }
+    ///         });
+    ///});
+    ///# handle.join().unwrap();
+    ///```
+    pub fn start_with_timeout<W: WaitStrategy>(&self, wait_strategy: W, timeout: Duration,
+                                               mut f: impl FnMut(&[T]) -> Result<(), TurbineError>,
+                                               on_timeout: impl FnMut(u64) -> Result<(), TurbineError>) {
+        self.run_loop(wait_strategy, Some(timeout), |data, sequence, _| self.guard_each(data, sequence, |data, _| f(data)), on_timeout)
+    }
+
+    /// Begin waiting for data, passing each slice along with its position in the ring buffer.
+    ///
+    /// This behaves like `start`, but the closure also receives the absolute sequence of the
+    /// first element in the slice (so `data[i]` is sequence `sequence + i`) and a flag which is
+    /// true if the slice ends the batch that is currently available.  When a batch wraps around
+    /// the end of the ring buffer it is delivered as two slices, and only the second is flagged.
+    /// This lets consumers such as journalers flush or fsync once per batch rather than per slice.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///# use turbine::{Turbine, Slot, BusyWait, TurbineError};
+    ///# use std::thread;
+    ///# struct TestSlot { value: isize }
+    ///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+    ///# let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+    ///# let e1 = t.ep_new().unwrap();
+    ///# let event_processor = t.ep_finalize(e1).unwrap();
+    ///let handle = thread::spawn(move || {
+    ///     event_processor.start_sequenced::<BusyWait>(|data: &[TestSlot], sequence: u64, end_of_batch: bool| -> Result<(), TurbineError> {
+    ///         assert!(sequence == 0);
+    ///         assert!(end_of_batch);
+    ///         Err(TurbineError::Shutdown)
+    ///     });
+    ///});
+    ///# t.write(TestSlot { value: 19 }).unwrap();
+    ///# handle.join().unwrap();
+    ///```
+    pub fn start_sequenced<W: WaitStrategy>(&self, f: impl FnMut(&[T], u64, bool) -> Result<(), TurbineError>) {
+        self.start_sequenced_with(self.wait_strategy::<W>(), f)
+    }
+
+    /// Begin waiting for sequenced data, using a pre-configured WaitStrategy.
+    ///
+    /// This behaves exactly like `start_sequenced`, but the supplied WaitStrategy instance is used.
+    pub fn start_sequenced_with<W: WaitStrategy>(&self, wait_strategy: W, mut f: impl FnMut(&[T], u64, bool) -> Result<(), TurbineError>) {
+        self.run_loop(wait_strategy, None,
+            |data, sequence, available| self.guard_each(data, sequence, |data, sequence| f(data, sequence, sequence + data.len() as u64 == available)),
+            |_| Ok(()))
+    }
+
+    /// Drive an EventHandler with data from the ring buffer.
+    ///
+    /// This is an alternative to `start` which calls the handler once per event, rather than
+    /// passing slices to a closure.  `on_start` is called before the first wait, `on_batch_start`
+    /// before each batch and `on_event` for each event.  If the handler specifies a `timeout`,
+    /// `on_timeout` is called whenever no data arrives within that duration.
+    ///
+    /// `on_shutdown` is always called exactly once when the loop ends, whether the Turbine was
+    /// halted, the end of the stream was reached, the Turbine was poisoned, or the handler
+    /// returned an Err from `on_event` or `on_timeout`.  Only a panic which is not caught by an
+    /// ExceptionHandler skips it, since it unwinds out of `run`.
+    ///
+    /// The handler is returned once the EventProcessor shuts down, so any state it accumulated
+    /// can be inspected.  See `EventHandler` for an example.
+    pub fn run<W: WaitStrategy, H: EventHandler<T>>(&self, handler: H) -> H {
+        self.run_with(self.wait_strategy::<W>(), handler)
+    }
+
+    /// Drive an EventHandler with data from the ring buffer, using a pre-configured WaitStrategy.
+    ///
+    /// This behaves exactly like `run`, but the supplied WaitStrategy instance is used.
+    pub fn run_with<W: WaitStrategy, H: EventHandler<T>>(&self, wait_strategy: W, mut handler: H) -> H {
+        handler.on_start();
+
+        let timeout = handler.timeout();
+        let handler_cell = std::cell::RefCell::new(&mut handler);
+        let mut batch_end: Option<u64> = None;
+
+        self.run_loop(wait_strategy, timeout,
+            |data: &[T], sequence: u64, available: u64| -> Result<(), u64> {
+                let mut h = handler_cell.borrow_mut();
+
+                // A rollover delivers one batch as two slices
+                if batch_end != Some(available) {
+                    h.on_batch_start(available - sequence);
+                    batch_end = Some(available);
+                }
+
+                for (i, event) in data.iter().enumerate() {
+                    let seq = sequence + i as u64;
+                    match panic::catch_unwind(AssertUnwindSafe(|| h.on_event(event, seq, seq + 1 == available))) {
+                        Ok(status) => self.poison_on(status).map_err(|_| seq + 1)?,
+                        Err(payload) => self.on_panic(payload, seq, event).map_err(|_| seq)?
+                    }
+                }
+                Ok(())
+            },
+            |sequence: u64| self.poison_on(handler_cell.borrow_mut().on_timeout(sequence)));
+
+        handler.on_shutdown();
+        handler
+    }
+
+    /// Poison the Turbine if an EventHandler failed with `TurbineError::Poisoned`
+    fn poison_on(&self, status: Result<(), TurbineError>) -> Result<(), TurbineError> {
+        if status == Err(TurbineError::Poisoned) {
+            debug!("Poisoning the Turbine after a handler failed");
+            self.alert.poison();
+        }
+        status
+    }
+
+    /// Call `f` on a slice starting at `sequence`, or on each of its events in turn
+    ///
+    /// Without an ExceptionHandler `f` receives the whole slice and a panic carries on
+    /// unwinding.  With one, each event is guarded on its own, so a panic is reported
+    /// with the event which caused it and `Skip` only skips that event.  An Err from
+    /// `f` stops the EP after whatever `f` was given.
+    fn guard_each(&self, data: &[T], sequence: u64, mut f: impl FnMut(&[T], u64) -> Result<(), TurbineError>) -> Result<(), u64> {
+        if self.exception_handler.borrow().is_none() {
+            return f(data, sequence).map_err(|_| sequence + data.len() as u64);
+        }
+
+        for (i, slot) in data.iter().enumerate() {
+            let event = std::slice::from_ref(slot);
+            let seq = sequence + i as u64;
+            self.guard(event, seq, || f(event, seq))?;
+        }
+        Ok(())
+    }
+
+    /// Call `f` on a slice starting at `sequence`, passing any panic to the ExceptionHandler
+    ///
+    /// An Err from `f` stops the EP after the slice, while a panic which is not skipped
+    /// stops it before the slice, so that dependent EPs never see the failed events.
+    pub(crate) fn guard(&self, data: &[T], sequence: u64, f: impl FnOnce() -> Result<(), TurbineError>) -> Result<(), u64> {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(status) => status.map_err(|_| sequence + data.len() as u64),
+            Err(payload) => match data.first() {
+                Some(slot) => self.on_panic(payload, sequence, slot).map_err(|_| sequence),
+                None => panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Ask the ExceptionHandler what to do about a panic while processing `sequence`
+    ///
+    /// Without an ExceptionHandler the panic carries on unwinding.  Otherwise the
+    /// result tells the main loop whether to continue.
+    fn on_panic(&self, payload: Box<dyn Any + Send>, sequence: u64, slot: &T) -> Result<(), TurbineError> {
+        let mut exception_handler = self.exception_handler.borrow_mut();
+        let handler = match exception_handler.as_mut() {
+            Some(handler) => handler,
+            None => {
+                drop(exception_handler);
+                panic::resume_unwind(payload)
+            }
+        };
+
+        handler.on_panic(&*payload, sequence, slot).apply(&self.alert, sequence)
+    }
 
-        let wait_strategy: W = WaitStrategy::new(capacity);
+    /// The read-only main loop, shared by all the `start` and `run` variants
+    ///
+    /// `f` is called with each slice of available data, the absolute sequence of the
+    /// first element in the slice and the end of the batch the slice belongs to.  It
+    /// returns an Err to stop, as for `process_loop`.
+    pub(crate) fn run_loop<W: WaitStrategy>(&self, wait_strategy: W, timeout: Option<Duration>,
+                                 mut f: impl FnMut(&[T], u64, u64) -> Result<(), u64>,
+                                 on_timeout: impl FnMut(u64) -> Result<(), TurbineError>) {
+        self.process_loop(wait_strategy, timeout, |from, to, sequence, available| {
+            // This is safe because the Producer task cannot invalidate these slots
+            // before we increment our cursor.  Since the slice is borrowed out, we
+            // know it will be returned after the function call ends.  The slice will
+            // be dropped after the unsafe block, and *then* we increment our cursor
+            let data: &[T] = unsafe { self.ring.get(from, to) };
+            f(data, sequence, available)
+        }, on_timeout)
+    }
 
-        let ref dep_eps = self.graph.as_slice()[self.token];
+    /// The EventProcessor's main loop
+    ///
+    /// Waits for data and calls `f` with the ring buffer range `[from, to)` that is ready,
+    /// the absolute sequence of `from` and the end of the batch the range belongs to.  A
+    /// batch which wraps around the end of the ring buffer is passed as two ranges.
+    ///
+    /// To stop, `f` returns Err with the sequence the EP stopped at: every event before
+    /// it has been processed, and the cursor is left there so that dependent EPs do not
+    /// process the rest of the batch either.
+    fn process_loop<W: WaitStrategy>(&self, wait_strategy: W, timeout: Option<Duration>,
+                                     mut f: impl FnMut(usize, usize, u64, u64) -> Result<(), u64>,
+                                     mut on_timeout: impl FnMut(u64) -> Result<(), TurbineError>) {
+        let capacity = self.ring.get_capacity();
+
+        let dep_eps = &self.graph[self.token];
         let mut deps: Vec<&AtomicUsize> = Vec::with_capacity(dep_eps.len());
         for ep in dep_eps.iter() {
-            deps.push(&(*self.cursors).as_slice()[*ep]);
+            deps.push(&self.cursors[*ep]);
         }
-        drop(dep_eps);
 
-        let ref cursor = &(*self.cursors).as_slice()[self.token + 1];
+        let cursor = &self.cursors[self.token + 1];
 
-        let mask: u64 = capacity as u64 - 1;
+        let indexer = self.ring.indexer();
         let mut internal_cursor = cursor.load(Ordering::SeqCst) as u64;
         let mut rollover = (false, 0);
 
         loop {
+            if self.alert.is_ended(internal_cursor) {
+                debug!("              Stream ended, stopping @ {}", internal_cursor);
+                break;
+            }
             debug!("              Current: {}, waiting on: {}", internal_cursor, internal_cursor);
 
-            let available = wait_strategy.wait_for(internal_cursor, &deps);
+            let available = match timeout {
+                None => wait_strategy.wait_for(internal_cursor, &deps, &self.alert),
+                Some(t) => match wait_strategy.wait_for_timeout(internal_cursor, &deps, &self.alert, t) {
+                    Ok(v) => v,
+                    Err(_) => {
+                        debug!("							Timed out waiting for {}", internal_cursor);
+                        if on_timeout(internal_cursor).is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+            };
             debug!("							Available: {}", available);
 
-            let from = (internal_cursor & mask) as usize;
-            let mut to = (available & mask) as usize;
+            // The wait was interrupted because the stream ended
+            if available == internal_cursor {
+                continue;
+            }
+
+            let from = indexer.position(internal_cursor);
+            let mut to = indexer.position(available);
 
             debug!("              from: {}, to: {} -- {}", from, to, (to < from));
             if to < from {
@@ -99,34 +463,118 @@ impl<T: Slot> EventProcessor<T> {
 
             debug!("              Post-modification from: {}, to: {} -- {}", from, to, (to < from));
 
-            // This is safe because the Producer task cannot invalidate these slots
-            // before we increment our cursor.  Since the slice is borrowed out, we
-            // know it will be returned after the function call ends.  The slice will
-            // be dropped after the unsafe block, and *then* we increment our cursor
-            let mut status = unsafe {
-                let data: &[T] = self.ring.get(from, to);
-                f(data)
-            };
+            let mut status = f(from, to, internal_cursor, available);
 
-            if rollover.0 == true {
+            if rollover.0 {
                 debug!("ROlLOVER GET");
-                status = unsafe {
-                    let data: &[T] = self.ring.get(0, rollover.1);
-                    f(data)
-                };
+                // A batch which ends exactly at the end of the ring leaves nothing to wrap
+                if status.is_ok() && rollover.1 > 0 {
+                    status = f(0, rollover.1, internal_cursor + (to - from) as u64, available);
+                }
                 rollover = (false,0);
             }
 
-            internal_cursor = available;
+            internal_cursor = match status {
+                Ok(()) => available,
+                Err(stopped) => stopped
+            };
             cursor.store(internal_cursor as usize, Ordering::SeqCst);
-            debug!("					Finished processing event.  Cursor @ {} ({})", available, available & mask);
+            self.notifier.notify_all();
+            debug!("					Finished processing event.  Cursor @ {} ({})", available, indexer.position(available));
 
-            match status {
-                Err(_) => break,
-                Ok(_) => {}
-            };
+            if status.is_err() {
+                break;
+            }
 
         }
         debug!("BusyWait::end");
     }
 }
+
+/// An EventProcessor which is the sole writer of its stage in the dependency graph
+///
+/// Obtained from `Turbine::ep_finalize_mut`, which only succeeds if every other
+/// EventProcessor is ordered before or after this one.  Since nothing else can
+/// touch a sequence while this EP holds it, the data is handed out as `&mut [T]`
+/// and may be enriched in-place for the EPs which depend on this one.
+pub struct EventProcessorMut<T> {
+    inner: EventProcessor<T>
+}
+
+impl<T: Send> EventProcessorMut<T> {
+
+    /// Wrap an EventProcessor which has been validated as the sole writer of its stage.
+    ///
+    /// This is for internal use only.
+    pub(crate) fn new(inner: EventProcessor<T>) -> EventProcessorMut<T> {
+        EventProcessorMut { inner }
+    }
+
+    /// Begin waiting for data to arrive from the ring buffer, with mutable access to it.
+    ///
+    /// This behaves like `EventProcessor::start`, except that the closure receives a
+    /// mutable slice.  Any changes are visible to dependent EPs once the closure returns.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///# use turbine::{Turbine, Slot, BusyWait, TurbineError};
+    ///# use std::thread;
+    ///# struct TestSlot { value: isize }
+    ///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+    ///# let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+    ///let decode = t.ep_new().unwrap();
+    ///let decoder = t.ep_finalize_mut(decode).unwrap();
+    ///
+    ///let handle = thread::spawn(move || {
+    ///     decoder.start::<BusyWait>(|data: &mut [TestSlot]| -> Result<(), TurbineError> {
+    ///         for x in data.iter_mut() {
+    ///             x.value *= 2;
+    ///         }
+    ///         Err(TurbineError::Shutdown)
+    ///     });
+    ///});
+    ///# t.write(TestSlot { value: 19 }).unwrap();
+    ///# handle.join().unwrap();
+    ///```
+    pub fn start<W: WaitStrategy>(&self, f: impl FnMut(&mut [T]) -> Result<(), TurbineError>) {
+        self.start_with(self.wait_strategy::<W>(), f)
+    }
+
+    /// Instantiate WaitStrategy `W` for this EventProcessor with its default configuration.
+    pub fn wait_strategy<W: WaitStrategy>(&self) -> W {
+        self.inner.wait_strategy::<W>()
+    }
+
+    /// Catch panics from the closure and pass them to `handler`, see
+    /// `EventProcessor::with_exception_handler`.
+    pub fn with_exception_handler(self, handler: impl ExceptionHandler<T> + Send + 'static) -> EventProcessorMut<T> {
+        EventProcessorMut { inner: self.inner.with_exception_handler(handler) }
+    }
+
+    /// Begin waiting for data with mutable access, using a pre-configured WaitStrategy.
+    ///
+    /// This behaves exactly like `start`, but the supplied WaitStrategy instance is used.
+    pub fn start_with<W: WaitStrategy>(&self, wait_strategy: W, mut f: impl FnMut(&mut [T]) -> Result<(), TurbineError>) {
+        let ring = &self.inner.ring;
+        self.inner.process_loop(wait_strategy, None, |from, to, sequence, _| {
+            // In addition to the reasoning in `run_loop`, the graph was validated when this
+            // EP was finalized: every other EP has either finished with these slots or is
+            // waiting on our cursor, so this is the only live reference to them.
+            let data: &mut [T] = unsafe { ring.get_slice_mut(from, to) };
+            if self.inner.exception_handler.borrow().is_none() {
+                return f(data).map_err(|_| sequence + data.len() as u64);
+            }
+
+            // Guard each event on its own, as `EventProcessor::guard_each` does
+            for i in 0..data.len() {
+                let seq = sequence + i as u64;
+                match panic::catch_unwind(AssertUnwindSafe(|| f(&mut data[i..i + 1]))) {
+                    Ok(status) => status.map_err(|_| seq + 1)?,
+                    Err(payload) => self.inner.on_panic(payload, seq, &data[i]).map_err(|_| seq)?
+                }
+            }
+            Ok(())
+        }, |_| Ok(()))
+    }
+}
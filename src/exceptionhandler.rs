@@ -0,0 +1,105 @@
+use std::any::Any;
+use crate::control::Alert;
+use crate::error::TurbineError;
+
+/// What an EventProcessor should do after its closure or handler panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionAction {
+    /// Carry on with the next event, as though the failed one had been processed
+    Skip,
+
+    /// Stop this EventProcessor, as though it had returned Err(TurbineError::Shutdown).
+    /// Other EPs keep running.
+    Halt,
+
+    /// Stop every EventProcessor and make blocking writes fail with
+    /// `TurbineError::Poisoned`, rather than waiting forever for slots the
+    /// failed EP will never release
+    Poison
+}
+
+impl ExceptionAction {
+
+    /// Carry out the action after a panic at `sequence`
+    ///
+    /// Returns Err if the EventProcessor or worker should stop.
+    pub(crate) fn apply(self, alert: &Alert, sequence: u64) -> Result<(), TurbineError> {
+        match self {
+            ExceptionAction::Skip => {
+                debug!("Skipping {} after a panic", sequence);
+                Ok(())
+            },
+            ExceptionAction::Halt => {
+                debug!("Halting after a panic at {}", sequence);
+                Err(TurbineError::Shutdown)
+            },
+            ExceptionAction::Poison => {
+                debug!("Poisoning the Turbine after a panic at {}", sequence);
+                alert.poison();
+                Err(TurbineError::Poisoned)
+            }
+        }
+    }
+}
+
+/// Decides how an EventProcessor recovers from a panic
+///
+/// Without an ExceptionHandler a panic unwinds out of `start` or `run` as usual,
+/// leaving the EP's cursor where it was.  Once one is installed with
+/// `EventProcessor::with_exception_handler` (or `WorkerPool::with_exception_handler`),
+/// panics are caught and handed to `on_panic` along with the event that caused them.
+///
+/// Every event is guarded on its own, so `sequence` and `slot` are exactly the event
+/// which failed, and `Skip` only skips that event.  To make this possible, closures
+/// passed to `start` are handed one event at a time while an ExceptionHandler is
+/// installed, rather than a whole slice.
+///
+/// After `Halt` or `Poison` the EP's cursor is left before the failed event, so EPs
+/// which depend on it never process it.
+///
+/// Closures with the same signature as `on_panic` are ExceptionHandlers too.
+///
+///# Example
+///
+///```
+///# use turbine::{Turbine, Slot, BusyWait, TurbineError, ExceptionAction};
+///# use std::any::Any;
+///# use std::thread;
+///# struct TestSlot { value: isize }
+///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+///let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+///let e1 = t.ep_new().unwrap();
+///let event_processor = t.ep_finalize(e1).unwrap()
+///    .with_exception_handler(|_: &(dyn Any + Send), sequence: u64, slot: &TestSlot| {
+///        println!("event {} ({}) failed, giving up", sequence, slot.value);
+///        ExceptionAction::Poison
+///    });
+///
+///let handle = thread::spawn(move || {
+///    event_processor.start::<BusyWait>(|data: &[TestSlot]| -> Result<(), TurbineError> {
+///        assert!(data[0].value >= 0, "negative value");
+///        Ok(())
+///    });
+///});
+///
+///t.write(TestSlot { value: -5 }).unwrap();
+///handle.join().unwrap();
+///
+///// The EP is gone, so further writes fail rather than filling the ring and waiting forever
+///assert!(t.write(TestSlot { value: 5 }) == Err(TurbineError::Poisoned));
+///```
+pub trait ExceptionHandler<T> {
+
+    /// Called with the panic payload, the sequence and the slot being processed
+    /// when the EventProcessor panicked.
+    fn on_panic(&mut self, payload: &(dyn Any + Send), sequence: u64, slot: &T) -> ExceptionAction;
+}
+
+impl<T, F: FnMut(&(dyn Any + Send), u64, &T) -> ExceptionAction> ExceptionHandler<T> for F {
+    fn on_panic(&mut self, payload: &(dyn Any + Send), sequence: u64, slot: &T) -> ExceptionAction {
+        self(payload, sequence, slot)
+    }
+}
+
+/// A boxed ExceptionHandler, as stored by an EventProcessor
+pub type BoxedExceptionHandler<T> = Box<dyn ExceptionHandler<T> + Send>;
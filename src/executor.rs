@@ -0,0 +1,214 @@
+use std::any::Any;
+use std::thread::{self, JoinHandle};
+use crate::control::Alert;
+use crate::dsl::{Processor, BoundProcessor, BoxedHandler};
+use crate::error::TurbineError;
+
+/// EventProcessors running on their own threads, as returned by `Turbine::start_all`
+///
+/// Each processor runs until its handler returns an Err.  `join` waits for all of
+/// them and reports how each one exited.
+pub struct RunningProcessors<T> {
+    threads: Vec<(Processor, JoinHandle<BoxedHandler<T>>)>
+}
+
+impl<T: Send + Sync + 'static> RunningProcessors<T> {
+
+    /// Start each processor on its own named thread.
+    ///
+    /// If a thread cannot be spawned, `alert` is halted and the processors which
+    /// already started are joined before the error is returned.
+    ///
+    /// This is for internal use only.
+    pub(crate) fn spawn(processors: Vec<BoundProcessor<T>>,
+                        alert: &Alert) -> Result<RunningProcessors<T>, TurbineError> {
+        let mut threads = Vec::with_capacity(processors.len());
+        for bound in processors {
+            let processor = bound.processor();
+            let spawned = thread::Builder::new()
+                .name(format!("turbine-processor-{}", processor.token()))
+                .spawn(move || bound.start());
+
+            match spawned {
+                Ok(handle) => threads.push((processor, handle)),
+                Err(e) => {
+                    debug!("Failed to spawn {:?}, halting the processors already running", processor);
+                    alert.halt();
+                    for (_, handle) in threads {
+                        let _ = handle.join();
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(RunningProcessors { threads })
+    }
+}
+
+impl<T> RunningProcessors<T> {
+
+    /// Handles to the running processors, in the order they were added to the pipeline
+    pub fn processors(&self) -> Vec<Processor> {
+        self.threads.iter().map(|t| t.0).collect()
+    }
+
+    /// Check if `processor` has stopped, either by shutting down or by panicking.
+    ///
+    /// Returns None if `processor` is not one of the running processors.
+    pub fn is_finished(&self, processor: Processor) -> Option<bool> {
+        self.threads.iter()
+            .find(|t| t.0 == processor)
+            .map(|t| t.1.is_finished())
+    }
+
+    /// Wait for every processor to stop, and report how each one exited.
+    ///
+    /// Exits are returned in the same order as `processors()`.
+    pub fn join(self) -> Vec<ProcessorExit<T>> {
+        self.threads.into_iter()
+            .map(|(processor, handle)| ProcessorExit { processor, result: handle.join() })
+            .collect()
+    }
+}
+
+/// How a processor started by `Turbine::start_all` exited
+pub struct ProcessorExit<T> {
+    processor: Processor,
+    result: thread::Result<BoxedHandler<T>>
+}
+
+impl<T> ProcessorExit<T> {
+
+    /// The processor which exited
+    pub fn processor(&self) -> Processor {
+        self.processor
+    }
+
+    /// True if the processor's handler panicked
+    pub fn is_panic(&self) -> bool {
+        self.result.is_err()
+    }
+
+    /// The panic message, if the handler panicked with a string
+    pub fn panic_message(&self) -> Option<&str> {
+        match self.result {
+            Ok(_) => None,
+            Err(ref payload) => panic_message(&**payload)
+        }
+    }
+
+    /// Recover the handler if the processor shut down normally, so any state it
+    /// accumulated can be inspected.
+    pub fn into_handler(self) -> Option<BoxedHandler<T>> {
+        self.result.ok()
+    }
+
+    /// The raw result of joining the processor's thread
+    pub fn into_result(self) -> thread::Result<BoxedHandler<T>> {
+        self.result
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{Turbine, Slot, EventHandler, BlockingWait, TurbineError, ExceptionAction};
+    use std::any::Any;
+    use std::thread;
+
+    struct TestSlot {
+        pub value: isize
+    }
+
+    impl Slot for TestSlot {
+        fn new() -> TestSlot {
+            TestSlot { value: -1 }
+        }
+    }
+
+    struct Counter {
+        seen: isize,
+        panic_on: Option<isize>
+    }
+
+    impl EventHandler<TestSlot> for Counter {
+        fn on_event(&mut self, event: &TestSlot, _: u64, _: bool) -> Result<(), TurbineError> {
+            if self.panic_on == Some(event.value) {
+                panic!("bad event {}", event.value);
+            }
+            self.seen += 1;
+            if event.value == 9 { Err(TurbineError::Shutdown) } else { Ok(()) }
+        }
+    }
+
+    #[test]
+    fn test_start_all() {
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+        t.handle_events_with((Counter { seen: 0, panic_on: None }, Counter { seen: 0, panic_on: None })).unwrap()
+            .with_wait_strategy::<BlockingWait>()
+            .then(Counter { seen: 0, panic_on: None }).unwrap()
+            .with_wait_strategy::<BlockingWait>();
+
+        let running = t.start_all().unwrap();
+        let processors = running.processors();
+        assert!(processors.iter().map(|p| p.token()).collect::<Vec<_>>() == vec![0, 1, 2]);
+        assert!(running.is_finished(processors[0]) == Some(false));
+
+        for i in 0..10 {
+            t.write(TestSlot { value: i }).unwrap();
+        }
+
+        for exit in running.join() {
+            assert!(!exit.is_panic());
+            assert!(exit.panic_message().is_none());
+            assert!(exit.into_handler().is_some());
+        }
+    }
+
+    #[test]
+    fn test_start_all_reports_panics() {
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+        t.handle_events_with((Counter { seen: 0, panic_on: None }, Counter { seen: 0, panic_on: Some(3) })).unwrap()
+            .with_wait_strategy::<BlockingWait>();
+
+        let running = t.start_all().unwrap();
+        for i in 0..10 {
+            t.write(TestSlot { value: i }).unwrap();
+        }
+
+        // Wait for the panicking processor without joining the other one
+        let failing = running.processors()[1];
+        while running.is_finished(failing) != Some(true) {
+            thread::yield_now();
+        }
+
+        let exits = running.join();
+        assert!(!exits[0].is_panic());
+        assert!(exits[1].is_panic());
+        assert!(exits[1].panic_message() == Some("bad event 3"));
+        assert!(exits[1].processor() == failing);
+    }
+
+    #[test]
+    fn test_start_all_with_exception_handler() {
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+        t.handle_events_with((Counter { seen: 0, panic_on: Some(3) },)).unwrap()
+            .with_wait_strategy::<BlockingWait>()
+            .with_exception_handler(|_: &(dyn Any + Send), _: u64, _: &TestSlot| ExceptionAction::Skip);
+
+        let running = t.start_all().unwrap();
+        for i in 0..10 {
+            t.write(TestSlot { value: i }).unwrap();
+        }
+
+        let exit = running.join().pop().unwrap();
+        assert!(!exit.is_panic());
+    }
+}
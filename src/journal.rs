@@ -0,0 +1,753 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::error::TurbineError;
+use crate::eventhandler::EventHandler;
+
+/// Every record starts with its payload length, a checksum and its sequence
+const HEADER_LEN: u64 = 16;
+
+const EXTENSION: &str = "journal";
+
+/// Segments are rolled once they grow past this many bytes, unless configured otherwise
+const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How often a Journal forces what it has appended onto stable storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Never fsync; the operating system writes the journal back in its own time.
+    /// Events survive the process crashing, but not the machine.
+    Never,
+
+    /// Fsync after every batch, before the EPs which depend on the Journal see it
+    EveryBatch,
+
+    /// Fsync at the end of a batch if at least this long has passed since the last one,
+    /// and whenever the EP has waited this long for the next batch.  A machine crash
+    /// loses about this long's worth of events at most, plus the batch in progress.
+    Interval(Duration)
+}
+
+/// An event which can be written to, and read back from, a Journal
+///
+/// Implemented for the primitive integer and float types and for `Vec<u8>`.
+///
+///## Example
+///
+///```
+///# use turbine::Journaled;
+///struct Trade {
+///    price: u64,
+///    quantity: u32
+///}
+///
+///impl Journaled for Trade {
+///    fn encode(&self, buf: &mut Vec<u8>) {
+///        buf.extend_from_slice(&self.price.to_le_bytes());
+///        buf.extend_from_slice(&self.quantity.to_le_bytes());
+///    }
+///
+///    fn decode(bytes: &[u8]) -> Option<Trade> {
+///        Some(Trade {
+///            price: u64::from_le_bytes(bytes.get(0..8)?.try_into().ok()?),
+///            quantity: u32::from_le_bytes(bytes.get(8..12)?.try_into().ok()?)
+///        })
+///    }
+///}
+///```
+pub trait Journaled: Sized {
+
+    /// Append the encoded event to `buf`
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decode an event which was encoded by `encode`, or None if `bytes` is not one
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! journaled_primitive(
+    ($($t:ty),*) => ($(
+        impl Journaled for $t {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> Option<$t> {
+                Some(<$t>::from_le_bytes(bytes.try_into().ok()?))
+            }
+        }
+    )*);
+);
+
+journaled_primitive!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+impl Journaled for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Vec<u8>> {
+        Some(bytes.to_vec())
+    }
+}
+
+/// FNV-1a over the sequence and payload of a record, so that a torn or damaged
+/// record is not mistaken for a valid one
+fn checksum(sequence: u64, payload: &[u8]) -> u32 {
+    sequence.to_le_bytes().iter().chain(payload.iter())
+        .fold(0x811c_9dc5, |hash: u32, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Segments are named after the first sequence they hold, padded so they sort by name
+/// Report an I/O error while appending, which poisons the Turbine
+fn io_failed(e: io::Error) -> TurbineError {
+    debug!("Journal: I/O error, poisoning the Turbine: {}", e);
+    TurbineError::Poisoned
+}
+
+fn segment_path(dir: &Path, first: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", first, EXTENSION))
+}
+
+/// Sync the entries of the directory `dir`, so that files created in it survive a crash
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories cannot be opened, or synced, as files here
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The segments of the journal in `dir`, oldest first, with the first sequence of each
+fn segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+            continue;
+        }
+        if let Some(first) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+            segments.push((first, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+enum Record {
+    /// A valid record with this sequence, whose payload was read
+    Event(u64),
+
+    /// The end of the segment
+    End,
+
+    /// An incomplete or damaged record, e.g. from a crash in the middle of a write
+    Torn
+}
+
+/// Reads the records of one segment in order
+struct SegmentReader {
+    reader: BufReader<File>,
+    offset: u64,
+    len: u64,
+    next: u64
+}
+
+impl SegmentReader {
+
+    fn open(path: &Path, first: u64) -> io::Result<SegmentReader> {
+        let file = File::open(path)?;
+        Ok(SegmentReader {
+            len: file.metadata()?.len(),
+            reader: BufReader::new(file),
+            offset: 0,
+            next: first
+        })
+    }
+
+    /// Read the next record, leaving its payload in `payload`
+    fn next(&mut self, payload: &mut Vec<u8>) -> io::Result<Record> {
+        if self.offset == self.len {
+            return Ok(Record::End);
+        }
+        if self.len - self.offset < HEADER_LEN {
+            return Ok(Record::Torn);
+        }
+
+        let mut header = [0; HEADER_LEN as usize];
+        self.reader.read_exact(&mut header)?;
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let sum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let sequence = u64::from_le_bytes([header[8], header[9], header[10], header[11],
+                                           header[12], header[13], header[14], header[15]]);
+
+        if sequence != self.next || self.len - self.offset - HEADER_LEN < len {
+            return Ok(Record::Torn);
+        }
+
+        payload.resize(len as usize, 0);
+        self.reader.read_exact(payload)?;
+        if checksum(sequence, payload) != sum {
+            return Ok(Record::Torn);
+        }
+
+        self.offset += HEADER_LEN + len;
+        self.next += 1;
+        Ok(Record::Event(sequence))
+    }
+
+    /// Skip to the end of the valid records, returning the offset where they end
+    fn valid_len(&mut self) -> io::Result<u64> {
+        let mut payload = vec![];
+        while let Record::Event(_) = self.next(&mut payload)? {}
+        Ok(self.offset)
+    }
+}
+
+/// An EventHandler which appends every event to a segmented log on disk
+///
+/// The Journal is normally the first stage of a pipeline, with the business logic
+/// depending on it, so nothing is acted on before it has been journaled.  Each batch
+/// is appended to the current segment with a single write, and synced according to
+/// the FsyncPolicy (`EveryBatch` by default).  Once a segment grows past the segment
+/// size a new one is started; old segments can simply be deleted once they are no
+/// longer needed for recovery.
+///
+/// Opening a Journal continues an existing one, after discarding any record torn by
+/// a crash.  A Turbine which has replayed the journal with `Turbine::recover` can
+/// journal to it again by passing the Replay to `with_replay`: the replayed events,
+/// which the journal already holds, are then skipped.  Any other event the journal
+/// already holds stops the EP with `TurbineError::JournalStale`, e.g. when a Turbine
+/// restarts from sequence zero without recovering, and an event beyond the end of the
+/// journal stops it with `TurbineError::JournalGap`, and an event which encodes to
+/// more than 4GiB stops it with `TurbineError::RecordTooLarge`.  An I/O error
+/// poisons the Turbine, since nothing after it can be journaled: writes then fail
+/// with `TurbineError::Poisoned` rather than wait for the Journal forever.
+///
+///## Example
+///
+///```
+///# use turbine::{Turbine, Journal, FsyncPolicy, EventHandler, BusyWait, TurbineError};
+///# use std::thread;
+///struct Total(u64);
+///
+///impl EventHandler<u64> for Total {
+///    fn on_event(&mut self, event: &u64, _: u64, _: bool) -> Result<(), TurbineError> {
+///        self.0 += event;
+///        Ok(())
+///    }
+///}
+///
+///let path = std::env::temp_dir().join(format!("turbine-journal-doc-{}", std::process::id()));
+///# let _ = std::fs::remove_dir_all(&path);
+///
+///// The first run journals everything before totalling it
+///let mut t: Turbine<u64> = Turbine::new(1024).unwrap();
+///let journal = Journal::open(&path).unwrap().with_fsync(FsyncPolicy::Never);
+///t.handle_events_with((journal,)).unwrap().then(Total(0)).unwrap();
+///let running = t.start_all().unwrap();
+///for i in 0..100 {
+///    t.write(i).unwrap();
+///}
+///drop(t);
+///running.join();
+///
+///// After a restart, the total is rebuilt from the journal
+///let mut t: Turbine<u64> = Turbine::new(1024).unwrap();
+///let replay = t.recover(&path, 0).unwrap();
+///let e1 = t.ep_new().unwrap();
+///let event_processor = t.ep_finalize(e1).unwrap();
+///let handle = thread::spawn(move || event_processor.run::<BusyWait, _>(Total(0)));
+///
+///for event in replay {
+///    t.write(event.unwrap()).unwrap();
+///}
+///drop(t);
+///assert!(handle.join().unwrap().0 == 4950);
+///# std::fs::remove_dir_all(&path).unwrap();
+///```
+pub struct Journal<T> {
+    dir: PathBuf,
+    file: File,
+    segment_len: u64,
+    segment_size: u64,
+    fsync: FsyncPolicy,
+    last_sync: Instant,
+    unsynced: bool,
+    next: u64,
+    replay_from: Option<u64>,
+    unsynced_dirs: Vec<PathBuf>,
+    buf: Vec<u8>,
+    _event: PhantomData<fn(&T)>
+}
+
+impl<T: Journaled> Journal<T> {
+
+    /// Open the journal in the directory `dir`, creating it if necessary.
+    ///
+    /// A new journal starts at sequence zero.  An existing one continues after its
+    /// last complete record.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Journal<T>, TurbineError> {
+        let dir = dir.as_ref();
+
+        // Whatever is created here must be synced along with the first batch, since
+        // it holds a segment which is synced
+        let mut unsynced_dirs = vec![];
+        if !dir.is_dir() {
+            unsynced_dirs.extend(dir.parent().filter(|p| !p.as_os_str().is_empty()).map(Path::to_path_buf));
+        }
+        fs::create_dir_all(dir).map_err(|e| TurbineError::io_at(dir, e))?;
+
+        let (first, path) = match segments(dir).map_err(|e| TurbineError::io_at(dir, e))?.pop() {
+            Some(segment) => segment,
+            None => {
+                unsynced_dirs.push(dir.to_path_buf());
+                (0, segment_path(dir, 0))
+            }
+        };
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)
+            .map_err(|e| TurbineError::io_at(&path, e))?;
+
+        let mut reader = SegmentReader::open(&path, first).map_err(|e| TurbineError::io_at(&path, e))?;
+        let segment_len = reader.valid_len()?;
+
+        // Discard the record torn by a crash, if there is one
+        file.set_len(segment_len)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Journal {
+            dir: dir.to_path_buf(),
+            file,
+            segment_len,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            fsync: FsyncPolicy::EveryBatch,
+            last_sync: Instant::now(),
+            unsynced: false,
+            next: reader.next,
+            replay_from: None,
+            unsynced_dirs,
+            buf: vec![],
+            _event: PhantomData
+        })
+    }
+
+    /// Sync the journal according to `policy`.
+    pub fn with_fsync(mut self, policy: FsyncPolicy) -> Journal<T> {
+        self.fsync = policy;
+        self
+    }
+
+    /// Start a new segment once the current one grows past `bytes`.
+    ///
+    /// Segments are only rolled between batches, so they may grow somewhat larger.
+    pub fn with_segment_size(mut self, bytes: u64) -> Journal<T> {
+        self.segment_size = bytes;
+        self
+    }
+
+    /// Skip the events `replay` is replaying into the Turbine, since the journal
+    /// already holds them.
+    ///
+    /// `replay` must have been returned by `Turbine::recover` for this journal's
+    /// directory.  Without it, every event the journal already holds is refused.
+    pub fn with_replay(mut self, replay: &Replay<T>) -> Journal<T> {
+        self.replay_from = Some(replay.from);
+        self
+    }
+
+    /// The sequence the next event appended to the journal will have.
+    pub fn next_sequence(&self) -> u64 {
+        self.next
+    }
+
+    /// Append the buffered batch to the current segment, then sync and roll it
+    /// as configured
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.write_all(&self.buf)?;
+        self.segment_len += self.buf.len() as u64;
+        self.buf.clear();
+        self.unsynced = true;
+
+        let sync = match self.fsync {
+            FsyncPolicy::Never => false,
+            FsyncPolicy::EveryBatch => true,
+            FsyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval
+        };
+        if sync {
+            self.sync()?;
+        }
+
+        if self.segment_len >= self.segment_size {
+            if self.fsync != FsyncPolicy::Never {
+                self.file.sync_data()?;
+                self.unsynced = false;
+            }
+            self.file = OpenOptions::new().write(true).create_new(true).open(segment_path(&self.dir, self.next))?;
+            self.segment_len = 0;
+            self.unsynced_dirs.push(self.dir.clone());
+
+            // Otherwise the new segment, along with everything later synced into it,
+            // could vanish from the directory in a crash
+            if self.fsync != FsyncPolicy::Never {
+                self.sync_dirs()?;
+            }
+            debug!("Journal: rolled to a new segment @ {}", self.next);
+        }
+        Ok(())
+    }
+
+    /// Force everything appended so far onto stable storage
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.sync_dirs()?;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
+
+    /// Sync the directories in which files have been created since the last sync
+    fn sync_dirs(&mut self) -> io::Result<()> {
+        for dir in self.unsynced_dirs.iter() {
+            sync_dir(dir)?;
+        }
+        self.unsynced_dirs.clear();
+        Ok(())
+    }
+}
+
+impl<T: Journaled> EventHandler<T> for Journal<T> {
+    fn on_event(&mut self, event: &T, sequence: u64, end_of_batch: bool) -> Result<(), TurbineError> {
+        if sequence > self.next {
+            return Err(TurbineError::JournalGap { expected: self.next, sequence });
+        }
+
+        // Only the events being replayed are expected to be in the journal already
+        if sequence < self.next && self.replay_from.is_none_or(|from| sequence < from) {
+            return Err(TurbineError::JournalStale { next: self.next, sequence });
+        }
+
+        if sequence == self.next {
+            let start = self.buf.len();
+            self.buf.extend_from_slice(&[0; HEADER_LEN as usize]);
+            event.encode(&mut self.buf);
+
+            let len = self.buf.len() - start - HEADER_LEN as usize;
+            let len = match u32::try_from(len) {
+                Ok(len) => len,
+                Err(_) => {
+                    self.buf.truncate(start);
+                    return Err(TurbineError::RecordTooLarge { len, max: u32::MAX as usize });
+                }
+            };
+            let sum = checksum(sequence, &self.buf[start + HEADER_LEN as usize..]);
+            self.buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+            self.buf[start + 4..start + 8].copy_from_slice(&sum.to_le_bytes());
+            self.buf[start + 8..start + 16].copy_from_slice(&sequence.to_le_bytes());
+            self.next += 1;
+        }
+
+        if end_of_batch && !self.buf.is_empty() {
+            self.flush().map_err(io_failed)?;
+        }
+        Ok(())
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        match self.fsync {
+            FsyncPolicy::Interval(interval) => Some(interval),
+            _ => None
+        }
+    }
+
+    /// The EP has been idle for a whole interval, so sync whatever the last batch
+    /// left behind rather than wait for another one
+    fn on_timeout(&mut self, _: u64) -> Result<(), TurbineError> {
+        if self.unsynced {
+            self.sync().map_err(io_failed)?;
+        }
+        Ok(())
+    }
+
+    fn on_shutdown(&mut self) {
+        let flushed = self.flush().and_then(|_| match self.fsync {
+            FsyncPolicy::Never => Ok(()),
+            _ => self.file.sync_data()
+        });
+        if let Err(e) = flushed {
+            debug!("Journal: failed to flush on shutdown: {}", e);
+        }
+    }
+}
+
+/// The events of a journal, in order, as returned by `Turbine::recover`
+///
+/// Each item is an event to be written back into the Turbine.  Iteration stops at
+/// the end of the journal, including at a record torn by a crash.  A damaged record
+/// anywhere else yields `TurbineError::CorruptJournal` and ends the iteration.
+pub struct Replay<T> {
+    segments: std::vec::IntoIter<(u64, PathBuf)>,
+    reader: Option<SegmentReader>,
+    from: u64,
+    payload: Vec<u8>,
+    done: bool,
+    _event: PhantomData<fn() -> T>
+}
+
+impl<T: Journaled> Replay<T> {
+
+    /// Open the journal in `dir` for replay from sequence `from`.
+    ///
+    /// `from` must lie within the journal, or be the sequence just after its end.
+    pub(crate) fn open(dir: &Path, from: u64) -> Result<Replay<T>, TurbineError> {
+        let mut segments = segments(dir).map_err(|e| TurbineError::io_at(dir, e))?;
+
+        let end = match segments.last() {
+            Some((first, path)) => SegmentReader::open(path, *first).and_then(|mut r| r.valid_len().map(|_| r.next))?,
+            None => 0
+        };
+        let start = segments.first().map_or(0, |&(first, _)| first);
+        if from < start || from > end {
+            return Err(TurbineError::NotJournaled(from));
+        }
+
+        // Skip the segments which end before `from`
+        let keep = segments.iter().rposition(|&(first, _)| first <= from).unwrap_or(0);
+        segments.drain(..keep);
+
+        Ok(Replay {
+            segments: segments.into_iter(),
+            reader: None,
+            from,
+            payload: vec![],
+            done: false,
+            _event: PhantomData
+        })
+    }
+
+    fn next_event(&mut self) -> Result<Option<T>, TurbineError> {
+        loop {
+            let reader = match self.reader {
+                Some(ref mut reader) => reader,
+                None => match self.segments.next() {
+                    Some((first, path)) => self.reader.insert(SegmentReader::open(&path, first)
+                        .map_err(|e| TurbineError::io_at(&path, e))?),
+                    None => return Ok(None)
+                }
+            };
+
+            match reader.next(&mut self.payload)? {
+                Record::Event(sequence) if sequence < self.from => continue,
+                Record::Event(sequence) => {
+                    return T::decode(&self.payload).map(Some).ok_or(TurbineError::CorruptJournal(sequence));
+                },
+                Record::End => {
+                    // The next segment has to carry on where this one stopped
+                    let next = reader.next;
+                    if let Some(&(first, _)) = self.segments.as_slice().first() {
+                        if first != next {
+                            return Err(TurbineError::CorruptJournal(next));
+                        }
+                    }
+                    self.reader = None;
+                },
+                Record::Torn if self.segments.as_slice().is_empty() => return Ok(None),
+                Record::Torn => return Err(TurbineError::CorruptJournal(reader.next))
+            }
+        }
+    }
+}
+
+impl<T: Journaled> Iterator for Replay<T> {
+    type Item = Result<T, TurbineError>;
+
+    fn next(&mut self) -> Option<Result<T, TurbineError>> {
+        if self.done {
+            return None;
+        }
+        let event = self.next_event().transpose();
+        self.done = !matches!(event, Some(Ok(_)));
+        event
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Journal, FsyncPolicy, Replay, segments, segment_path};
+    use crate::{Turbine, EventHandler, BlockingWait, TurbineError};
+    use std::env;
+    use std::thread;
+    use std::time::Duration;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    fn journal_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("turbine-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn append(journal: &mut Journal<u64>, events: std::ops::Range<u64>) {
+        for sequence in events.clone() {
+            journal.on_event(&(sequence * 10), sequence, sequence == events.end - 1).unwrap();
+        }
+    }
+
+    fn replay(dir: &Path, from: u64) -> Vec<u64> {
+        Replay::<u64>::open(dir, from).unwrap().map(|e| e.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_segments_roll_and_replay() {
+        let dir = journal_dir("roll");
+        let mut journal = Journal::<u64>::open(&dir).unwrap()
+            .with_fsync(FsyncPolicy::Interval(std::time::Duration::from_millis(1)))
+            .with_segment_size(100);
+
+        // 24 bytes per record, so every batch of five rolls the segment
+        for batch in 0..4 {
+            append(&mut journal, batch * 5..batch * 5 + 5);
+        }
+        drop(journal);
+
+        let firsts: Vec<u64> = segments(&dir).unwrap().into_iter().map(|(first, _)| first).collect();
+        assert!(firsts == vec![0, 5, 10, 15, 20]);
+
+        assert!(replay(&dir, 0) == (0..20).map(|s| s * 10).collect::<Vec<_>>());
+        assert!(replay(&dir, 12) == (12..20).map(|s| s * 10).collect::<Vec<_>>());
+        assert!(replay(&dir, 20).is_empty());
+        assert!(Replay::<u64>::open(&dir, 21).err() == Some(TurbineError::NotJournaled(21)));
+
+        // Damage before the last segment is reported rather than taken as the end
+        let path = dir.join(format!("{:020}.journal", 5));
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[30] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let events: Vec<_> = Replay::<u64>::open(&dir, 0).unwrap().collect();
+        assert!(events.len() == 7 && events[6] == Err(TurbineError::CorruptJournal(6)));
+        bytes[30] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        // Old segments can be removed once they are no longer needed
+        fs::remove_file(dir.join(format!("{:020}.journal", 0))).unwrap();
+        assert!(Replay::<u64>::open(&dir, 4).err() == Some(TurbineError::NotJournaled(4)));
+        assert!(replay(&dir, 5).len() == 15);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_created_directories_are_synced() {
+        let dir = journal_dir("dirsync");
+
+        // The journal directory and its first segment are both new
+        let mut journal = Journal::<u64>::open(&dir).unwrap().with_segment_size(100);
+        assert!(journal.unsynced_dirs == vec![env::temp_dir(), dir.clone()]);
+        append(&mut journal, 0..5);
+        assert!(journal.unsynced_dirs.is_empty());
+        drop(journal);
+
+        // Reopening creates nothing, and without fsync a rolled segment stays unsynced
+        let mut journal = Journal::<u64>::open(&dir).unwrap().with_fsync(FsyncPolicy::Never).with_segment_size(100);
+        assert!(journal.unsynced_dirs.is_empty());
+        append(&mut journal, 5..10);
+        assert!(journal.unsynced_dirs == vec![dir.clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_interval_syncs_when_idle() {
+        let dir = journal_dir("interval");
+        let interval = Duration::from_secs(3600);
+        let mut journal = Journal::<u64>::open(&dir).unwrap().with_fsync(FsyncPolicy::Interval(interval));
+        assert!(journal.timeout() == Some(interval));
+
+        // The batch is left unsynced until the EP has waited out the interval
+        append(&mut journal, 0..3);
+        assert!(journal.unsynced);
+        journal.on_timeout(3).unwrap();
+        assert!(!journal.unsynced && journal.unsynced_dirs.is_empty());
+
+        let journal = Journal::<u64>::open(&dir).unwrap().with_fsync(FsyncPolicy::EveryBatch);
+        assert!(journal.timeout().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_is_discarded() {
+        let dir = journal_dir("torn");
+        let mut journal = Journal::<u64>::open(&dir).unwrap().with_fsync(FsyncPolicy::Never);
+        append(&mut journal, 0..3);
+        drop(journal);
+
+        // Simulate a crash half way through writing the next record
+        let path = segments(&dir).unwrap().pop().unwrap().1;
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[9; 10]).unwrap();
+        assert!(replay(&dir, 0) == vec![0, 10, 20]);
+
+        let mut journal = Journal::<u64>::open(&dir).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() == len);
+        assert!(journal.next_sequence() == 3);
+
+        // Events the journal holds are refused unless they are being replayed
+        assert!(journal.on_event(&10, 1, true) == Err(TurbineError::JournalStale { next: 3, sequence: 1 }));
+        let mut journal = journal.with_replay(&Replay::open(&dir, 1).unwrap());
+
+        // Replayed events are skipped, and gaps are refused
+        append(&mut journal, 1..5);
+        assert!(journal.on_event(&0, 6, true) == Err(TurbineError::JournalGap { expected: 5, sequence: 6 }));
+        drop(journal);
+        assert!(replay(&dir, 0) == vec![0, 10, 20, 30, 40]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_without_recover() {
+        let dir = journal_dir("reopen");
+        let mut journal = Journal::<u64>::open(&dir).unwrap();
+        append(&mut journal, 0..10);
+        drop(journal);
+
+        // A Turbine restarted from zero must not have its events dropped
+        let mut journal = Journal::<u64>::open(&dir).unwrap();
+        assert!(journal.on_event(&7, 0, true) == Err(TurbineError::JournalStale { next: 10, sequence: 0 }));
+
+        // Nor may sequences before the ones being replayed be skipped
+        let mut journal = journal.with_replay(&Replay::open(&dir, 5).unwrap());
+        assert!(journal.on_event(&7, 3, true) == Err(TurbineError::JournalStale { next: 10, sequence: 3 }));
+        append(&mut journal, 5..12);
+        drop(journal);
+
+        assert!(replay(&dir, 0) == (0..12).map(|s| s * 10).collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_io_error_poisons() {
+        let dir = journal_dir("poison");
+        let journal = Journal::<u64>::open(&dir).unwrap().with_fsync(FsyncPolicy::Never).with_segment_size(1);
+
+        // The first batch rolls the segment, and finds the next one already there
+        for first in 1..=8 {
+            fs::write(segment_path(&dir, first), b"").unwrap();
+        }
+
+        let mut t: Turbine<u64> = Turbine::new(8).unwrap();
+        let e1 = t.ep_new().unwrap();
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let handle = thread::spawn(move || event_processor.run::<BlockingWait, _>(journal));
+
+        // Writers fail once the ring is full, rather than wait for the Journal forever
+        let failed = (0..100).map(|i| t.write(i)).find(Result::is_err);
+        assert!(failed == Some(Err(TurbineError::Poisoned)));
+        assert!(t.control().is_poisoned());
+        drop(handle.join().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
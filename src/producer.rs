@@ -0,0 +1,311 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::cmp::{min};
+use crate::ringbuffer::{RingBuffer, Indexer};
+use crate::waitstrategy::{ProducerWaitStrategy, Notifier, BusyWait, WriterWait};
+use crate::control::EndOfStream;
+use crate::cursors::Cursors;
+use crate::error::TurbineError;
+
+/// Tracks which sequences have been completed, when they may complete out of order
+///
+/// Each slot has a marker which records the last sequence completed in it.  The
+/// cursor is only ever advanced across a contiguous run of completed slots, so
+/// whoever waits on it never sees a sequence that is still in progress.  Used for
+/// sequences written by several Producers, and processed by several WorkerPool workers.
+pub(crate) struct AvailableSequences {
+    available: Vec<AtomicUsize>,
+    indexer: Indexer
+}
+
+impl AvailableSequences {
+
+    pub(crate) fn new(indexer: Indexer) -> AvailableSequences {
+        AvailableSequences {
+            available: (0..indexer.capacity()).map(|_| AtomicUsize::new(0)).collect(),
+            indexer
+        }
+    }
+
+    /// Mark `sequence` as completed and advance `cursor` as far as possible
+    ///
+    /// Markers store `sequence + 1`, so an untouched slot (zero) is never mistaken
+    /// for a completed one.  Whichever thread finishes the lowest outstanding
+    /// sequence moves the cursor forward, including across any later sequences
+    /// that other threads have already completed.
+    pub(crate) fn publish(&self, sequence: u64, cursor: &AtomicUsize) {
+        self.available[self.indexer.position(sequence)].store(sequence as usize + 1, Ordering::SeqCst);
+
+        let mut current = cursor.load(Ordering::SeqCst);
+        while self.available[self.indexer.position(current as u64)].load(Ordering::SeqCst) == current + 1 {
+            // Another thread may be advancing the cursor concurrently
+            match cursor.compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => current += 1,
+                Err(actual) => current = actual
+            }
+        }
+        debug!("Published {}, cursor @ {}", sequence, current);
+    }
+}
+
+/// Shared sequencing state for all Producers writing into the same Turbine
+///
+/// `claim` hands out the next sequence to write.  Since producers may finish
+/// writing their slots out of order, the root cursor is advanced through
+/// `AvailableSequences`, so EventProcessors never see a sequence that is still
+/// being written.
+///
+/// The Sequencer is dropped along with the last Producer, which signals
+/// end-of-stream to the EventProcessors.
+struct Sequencer {
+    claim: AtomicUsize,
+    published: AvailableSequences,
+    end_of_stream: EndOfStream
+}
+
+impl Sequencer {
+
+    fn new(indexer: Indexer, start: u64, end_of_stream: EndOfStream) -> Sequencer {
+        Sequencer {
+            claim: AtomicUsize::new(start as usize),
+            published: AvailableSequences::new(indexer),
+            end_of_stream
+        }
+    }
+
+    /// Claim the next sequence to write.
+    fn next(&self) -> u64 {
+        self.claim.fetch_add(1, Ordering::SeqCst) as u64
+    }
+}
+
+/// A cloneable handle which allows several threads to write into one Turbine
+///
+/// A Producer is obtained from `Turbine::into_producer()`.  Each clone may be
+/// sent to a different thread, turning Turbine into a Multi Producer Multi
+/// Consumer queue.  Sequences are claimed from a shared atomic counter and the
+/// EventProcessors only ever see contiguous, fully-written sequences.
+///
+/// Every clone carries its own copy of the Turbine's ProducerWaitStrategy.
+pub struct Producer<T, P = BusyWait> {
+    sequencer: Arc<Sequencer>,
+    cursors: Arc<Cursors>,
+    ring: Arc<RingBuffer<T>>,
+    notifier: Arc<Notifier>,
+    size: usize,
+    indexer: Indexer,
+    gate: u64,
+    wait_strategy: P
+}
+
+impl<T: Send, P: ProducerWaitStrategy> Producer<T, P> {
+
+    /// Instantiate a new Producer.
+    ///
+    /// This is for internal use only.  `start` is the first sequence which
+    /// has not yet been written, e.g. the position of the root cursor.
+    pub(crate) fn new(ring: Arc<RingBuffer<T>>, cursors: Arc<Cursors>, notifier: Arc<Notifier>, start: u64,
+                      wait_strategy: P, end_of_stream: EndOfStream) -> Producer<T, P> {
+        let size = ring.get_capacity();
+        let indexer = ring.indexer();
+
+        Producer::<T, P> {
+            sequencer: Arc::new(Sequencer::new(indexer, start, end_of_stream)),
+            cursors,
+            ring,
+            notifier,
+            size,
+            indexer,
+            gate: 0,
+            wait_strategy
+        }
+    }
+
+    /// Write data into Turbine
+    ///
+    /// Claims the next sequence, waits until it is free, writes the data and then
+    /// publishes it.  Like `Turbine::write`, this method will wait until a free
+    /// slot is open, using the ProducerWaitStrategy, and fails with
+    /// Err(TurbineError::Poisoned) if the Turbine has been poisoned, with
+    /// Err(TurbineError::Stopped) if it has been halted or shut down, or with
+    /// Err(TurbineError::NotFinalized) if there are no EventProcessors.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///# use turbine::{Turbine, Slot};
+    ///# use std::thread;
+    ///# struct TestSlot { value: isize }
+    ///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+    ///let e1 = t.ep_new().unwrap();
+    ///let event_processor = t.ep_finalize(e1).unwrap();
+    ///
+    ///let producer = t.into_producer().unwrap();
+    ///let handles: Vec<_> = (0..4).map(|i| {
+    ///    let mut p = producer.clone();
+    ///    thread::spawn(move || p.write(TestSlot { value: i }).unwrap())
+    ///}).collect();
+    ///
+    ///for h in handles {
+    ///    h.join().unwrap();
+    ///}
+    ///```
+    pub fn write(&mut self, data: T) -> Result<(), TurbineError> {
+        // Without an EP cursor to wait on, nothing would stop slots being overwritten
+        if self.cursors.len() < 2 {
+            return Err(TurbineError::NotFinalized);
+        }
+
+        let sequence = self.sequencer.next();
+
+        let mut wait = WriterWait::new();
+        loop {
+            // The claimed sequence is abandoned, but nothing will read it anyway
+            self.sequencer.end_of_stream.alert().check_running()?;
+            if self.can_write(sequence) {
+                break;
+            }
+            //debug!("Spin...");
+            wait.wait(&self.wait_strategy, &self.notifier);
+        }
+
+        debug!("Producer writing {} to {}", sequence, self.indexer.position(sequence));
+        unsafe {
+            self.ring.write(self.indexer.position(sequence), data);
+        }
+
+        self.sequencer.published.publish(sequence, &self.cursors[0]);
+        self.notifier.notify_all();
+        Ok(())
+    }
+
+    /// Check if the slot for `sequence` has been released by every EventProcessor
+    ///
+    /// The slowest EP cursor is cached in `gate` so that most writes do not need
+    /// to touch the EP Atomics at all.
+    fn can_write(&mut self, sequence: u64) -> bool {
+        if sequence - self.gate < self.size as u64 {
+            return true;
+        }
+
+        let mut min_cursor = sequence;
+        for v in self.cursors.iter().skip(1) {
+            min_cursor = min(min_cursor, v.load(Ordering::SeqCst) as u64);
+        }
+        self.gate = min_cursor;
+
+        sequence - min_cursor < self.size as u64
+    }
+}
+
+impl<T, P: Clone> Clone for Producer<T, P> {
+    fn clone(&self) -> Producer<T, P> {
+        Producer::<T, P> {
+            sequencer: self.sequencer.clone(),
+            cursors: self.cursors.clone(),
+            ring: self.ring.clone(),
+            notifier: self.notifier.clone(),
+            size: self.size,
+            indexer: self.indexer,
+            gate: self.gate,
+            wait_strategy: self.wait_strategy.clone()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{Turbine, Slot, BusyWait, ProducerWaitStrategy, YieldingWait, TurbineError};
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    struct TestSlot {
+        pub producer: usize,
+        pub value: isize
+    }
+
+    impl Slot for TestSlot {
+        fn new() -> TestSlot {
+            TestSlot {
+                producer: usize::MAX,
+                value: -1
+            }
+        }
+    }
+
+    fn run_producers<P>(mut t: Turbine<TestSlot, P>, producers: usize, per_producer: isize)
+        where P: ProducerWaitStrategy + Clone + Send + 'static {
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let (tx, rx) = channel();
+
+        thread::spawn(move || {
+            let mut counter = 0isize;
+            let mut last = vec![-1isize; producers];
+            event_processor.start::<BusyWait>(|data: &[TestSlot]| -> Result<(), TurbineError> {
+                for x in data.iter() {
+                    // Each producer's own writes must arrive in order
+                    assert!(last[x.producer] + 1 == x.value);
+                    last[x.producer] = x.value;
+                    counter += 1;
+                }
+
+                if counter >= producers as isize * per_producer {
+                    Err(TurbineError::Shutdown)
+                } else {
+                    Ok(())
+                }
+            });
+            tx.send(last).unwrap();
+        });
+
+        let producer = t.into_producer().unwrap();
+        let handles: Vec<_> = (0..producers).map(|id| {
+            let mut p = producer.clone();
+            thread::spawn(move || {
+                for i in 0..per_producer {
+                    p.write(TestSlot { producer: id, value: i }).unwrap();
+                }
+            })
+        }).collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let last = rx.recv().unwrap();
+        assert!(last.iter().all(|l| *l == per_producer - 1));
+    }
+
+    #[test]
+    fn test_single_producer() {
+        run_producers(Turbine::new(1024).unwrap(), 1, 5000);
+    }
+
+    #[test]
+    fn test_many_producers() {
+        run_producers(Turbine::new(1024).unwrap(), 4, 20000);
+    }
+
+    #[test]
+    fn test_many_producers_small_ring() {
+        run_producers(Turbine::new(8).unwrap(), 4, 250);
+    }
+
+    #[test]
+    fn test_many_yielding_producers_small_ring() {
+        run_producers(Turbine::with_wait_strategy(8, YieldingWait::new(10)).unwrap(), 4, 1000);
+    }
+
+    #[test]
+    fn test_into_producer_invalid_graph() {
+        let mut t: Turbine<TestSlot> = Turbine::new(8).unwrap();
+        let e1 = t.ep_new().unwrap();
+        t.ep_depends(e1, e1).unwrap();
+        assert!(t.into_producer().err() == Some(TurbineError::SelfDependency(e1)));
+    }
+}
@@ -1,3 +1,9 @@
+use std::cell::UnsafeCell;
+use std::path::Path;
+use std::sync::Arc;
+use crate::error::TurbineError;
+use crate::shm::Segment;
+
 macro_rules! is_pow2(
     ($x:ident) => (
       (($x != 0) && ($x & ($x - 1)) == 0)
@@ -6,12 +12,18 @@ macro_rules! is_pow2(
 
 /// A container for data inside the RingBuffer
 ///
-/// Slot must be implemented by the user.  A Slot implementation will provide
-/// a generic container which holds data to be placed inside of Turbine.  The
-/// contents of this container are irrelevant to Turbine, it simply needs to
-/// implement the Slot trait.
+/// A Slot implementation will provide a generic container which holds data to be
+/// placed inside of Turbine.  The contents of this container are irrelevant to
+/// Turbine, it simply needs to be able to create an initial instance to fill the
+/// buffer with.
+///
+/// Slot is implemented for every type which implements `Default`, so most event
+/// types need nothing extra.  Types without a sensible default can implement Slot
+/// themselves, or skip it entirely by building the buffer with `Turbine::with_factory`.
 ///
-/// Slot's must be Sendable since they are passed between tasks.
+/// Slot's must be Sendable since they are passed between tasks.  Slots are also read
+/// by several EventProcessors at once, so a Turbine can only hand them to other
+/// threads when they are `Sync` as well.
 ///
 /// *Note:* The size of the buffer in memory, allocated immediately upon instantiation
 /// of Turbine, will be `buffer_size * sizeof(YourSlot)`.
@@ -25,8 +37,9 @@ pub trait Slot: Send {
     ///##Example
     ///
     ///```
+    ///# use turbine::Slot;
     ///struct TestSlot {
-    /// pub value: int
+    /// pub value: isize
     ///}
     ///
     ///impl Slot for TestSlot {
@@ -40,42 +53,229 @@ pub trait Slot: Send {
     fn new() -> Self;
 }
 
+impl<T: Default + Send> Slot for T {
+    fn new() -> T {
+        T::default()
+    }
+}
+
+/// Maps monotonic sequences onto positions in the RingBuffer
+///
+/// Power-of-two buffers use a bit mask.  Buffers created with an exact capacity
+/// which is not a power of two fall back to the (slower) remainder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Indexer {
+    Mask(u64),
+    Modulo(u64)
+}
+
+impl Indexer {
+
+    fn for_capacity(capacity: usize) -> Indexer {
+        if is_pow2!(capacity) {
+            Indexer::Mask(capacity as u64 - 1)
+        } else {
+            Indexer::Modulo(capacity as u64)
+        }
+    }
+
+    /// The position of `sequence` in the buffer
+    #[inline]
+    pub(crate) fn position(self, sequence: u64) -> usize {
+        match self {
+            Indexer::Mask(mask) => (sequence & mask) as usize,
+            Indexer::Modulo(capacity) => (sequence % capacity) as usize
+        }
+    }
+
+    /// The number of positions in the buffer
+    pub(crate) fn capacity(self) -> usize {
+        match self {
+            Indexer::Mask(mask) => mask as usize + 1,
+            Indexer::Modulo(capacity) => capacity as usize
+        }
+    }
+}
+
 pub struct RingBuffer<T> {
-    entries: Vec<T>
+    entries: Entries<T>,
+    indexer: Indexer
 }
 
-impl<T: Slot + Send> RingBuffer<T> {
+/// Where the slots of a RingBuffer live
+enum Entries<T> {
+    Heap(Vec<UnsafeCell<T>>),
+    Shared(Arc<Segment>)
+}
 
-    pub fn new(size: usize) -> RingBuffer<T> {
-        let entries: Vec<T> = match size {
-            0 => panic!("Buffer Size must be greater than zero."),
-            s if !(is_pow2!(s)) => panic!("Buffer Size must be a power of two"),
-            _ => Vec::from_fn(size, |_| Slot::new())
-        };
+// The RingBuffer itself performs no synchronization.  Turbine and the
+// EventProcessors coordinate access through the cursors, which guarantees that
+// a slot is never written while it is being read.  Each slot lives in its own
+// UnsafeCell so that concurrent producers writing different slots never alias.
+// Several EventProcessors may hold `&T` to the same slot at once, so `T` must
+// itself be Sync.
+unsafe impl<T: Send + Sync> Sync for RingBuffer<T> {}
+
+impl<T: Send> RingBuffer<T> {
+
+    /// Allocate a RingBuffer of `size` Slots
+    ///
+    /// Returns `TurbineError::InvalidSize` if `size` is zero or not a power of two.
+    pub fn new(size: usize) -> Result<RingBuffer<T>, TurbineError> where T: Slot {
+        RingBuffer::with_factory(size, T::new)
+    }
+
+    /// Allocate a RingBuffer of `size` entries, each one created by `factory`
+    ///
+    /// Returns `TurbineError::InvalidSize` if `size` is zero or not a power of two.
+    pub fn with_factory(size: usize, factory: impl FnMut() -> T) -> Result<RingBuffer<T>, TurbineError> {
+        if !is_pow2!(size) {
+            return Err(TurbineError::InvalidSize(size));
+        }
+        Ok(RingBuffer::allocate(size, factory))
+    }
+
+    /// Allocate a RingBuffer of exactly `size` entries, each one created by `factory`
+    ///
+    /// Unlike `with_factory`, `size` does not have to be a power of two, so the buffer
+    /// can be sized to a memory budget.  Sequences are then mapped onto the buffer with
+    /// a remainder rather than a bit mask, which costs a division per lookup.  Power of
+    /// two sizes still use the mask.
+    ///
+    /// Returns `TurbineError::InvalidSize` if `size` is zero.
+    pub fn with_exact_capacity(size: usize, factory: impl FnMut() -> T) -> Result<RingBuffer<T>, TurbineError> {
+        if size == 0 {
+            return Err(TurbineError::InvalidSize(size));
+        }
+        Ok(RingBuffer::allocate(size, factory))
+    }
+
+    fn allocate(size: usize, factory: impl FnMut() -> T) -> RingBuffer<T> {
+        RingBuffer::<T> {
+            entries: Entries::Heap(std::iter::repeat_with(factory).take(size).map(UnsafeCell::new).collect()),
+            indexer: Indexer::for_capacity(size)
+        }
+    }
+
+    /// Create a RingBuffer of `size` Slots in a shared memory segment, backed by the
+    /// file at `path`.
+    ///
+    /// The file is created and sized to hold the ring, its cursors and a dependency
+    /// graph of up to `max_processors` EventProcessors.  Other processes attach to it
+    /// with `EventProcessor::attach` once a Turbine built on this ring has finalized its
+    /// graph.  The file is not removed when the RingBuffer is dropped.  An existing file
+    /// is never reused, since truncating it under a process which still has it mapped
+    /// would crash that process with SIGBUS; remove it once every process is done.
+    /// Placing the file on a tmpfs such as `/dev/shm` keeps it out of the page cache
+    /// writeback.
+    ///
+    /// Returns `TurbineError::InvalidSize` if `size` is zero or not a power of two,
+    /// and `TurbineError::Io` if the file already exists or cannot be created or mapped.
+    ///
+    /// # Safety
+    ///
+    /// `T` is copied between processes byte for byte, so it must be plain data: it
+    /// must not contain pointers, references (including `&'static str`) or anything
+    /// which owns memory, since their addresses mean nothing in another process.
+    /// Every process using the file must also read and write it as this same `T`,
+    /// built with the same layout.  `EventProcessor::attach` only checks the size and
+    /// alignment of the slots, so it cannot tell e.g. a `u32` from a `char`.
+    pub unsafe fn create_shared(path: impl AsRef<Path>, size: usize, max_processors: usize) -> Result<RingBuffer<T>, TurbineError>
+        where T: Slot + Copy {
+        if !is_pow2!(size) {
+            return Err(TurbineError::InvalidSize(size));
+        }
+        let segment = Segment::create(path.as_ref(), size, max_processors, T::new)?;
+        Ok(RingBuffer::shared(Arc::new(segment)))
+    }
 
+    /// Wrap the ring of a shared memory segment which was created for `T`
+    pub(crate) fn shared(segment: Arc<Segment>) -> RingBuffer<T> {
+        // This is safe because the segment was created, or checked on attach, for `T`
+        let size = unsafe { segment.ring::<T>() }.len();
         RingBuffer::<T> {
-            entries: entries
+            entries: Entries::Shared(segment),
+            indexer: Indexer::for_capacity(size)
+        }
+    }
+
+    /// The shared memory segment holding this RingBuffer, if it has one
+    pub(crate) fn segment(&self) -> Option<&Arc<Segment>> {
+        match self.entries {
+            Entries::Heap(_) => None,
+            Entries::Shared(ref segment) => Some(segment)
+        }
+    }
+
+    fn entries(&self) -> &[UnsafeCell<T>] {
+        match self.entries {
+            Entries::Heap(ref entries) => entries,
+            // This is safe because the segment was created, or checked on attach, for `T`
+            Entries::Shared(ref segment) => unsafe { segment.ring::<T>() }
         }
     }
 
     pub fn get_capacity(&self) -> usize {
-        let v: *mut Vec<T> = unsafe { self.entries.get() };
-        unsafe { (*v).len() }
+        self.entries().len()
+    }
+
+    /// How sequences map onto this buffer
+    pub(crate) fn indexer(&self) -> Indexer {
+        self.indexer
     }
 
-    // Unsafe because we have no guarantees the caller won't invalidate this slot
+    /// Borrow the slots in `[from, size)`
+    ///
+    /// # Safety
+    ///
+    /// Unsafe because we have no guarantees the caller won't invalidate this slot.
+    /// The caller must ensure the producer cannot write to these slots while the
+    /// slice is borrowed.
     pub unsafe fn get(&self, from: usize, size: usize) -> &[T] {
         debug!("              RingBuffer get({}, {})", from, size);
-        let v: *mut Vec<T> = self.entries.get();
-        (*v).slice(from, size)
+        let cells = &self.entries()[from..size];
+
+        // UnsafeCell<T> has the same in-memory representation as T
+        std::slice::from_raw_parts(cells.as_ptr() as *const T, cells.len())
     }
 
-    // Unsafe because we have no guarantees the caller won't invalidate this slot
+    /// Mutably borrow the slots in `[from, size)`
+    ///
+    /// # Safety
+    ///
+    /// Unsafe because we have no guarantees the caller won't invalidate these slots.
+    /// The caller must ensure neither the producer nor any other EventProcessor can
+    /// access these slots while the slice is borrowed.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_slice_mut(&self, from: usize, size: usize) -> &mut [T] {
+        debug!("              RingBuffer get_slice_mut({}, {})", from, size);
+        let cells = &self.entries()[from..size];
+
+        // UnsafeCell<T> has the same in-memory representation as T, and each
+        // cell grants interior mutability over its contents
+        std::slice::from_raw_parts_mut(UnsafeCell::raw_get(cells.as_ptr()), cells.len())
+    }
+
+    /// Mutably borrow the slot at `position`
+    ///
+    /// # Safety
+    ///
+    /// Unsafe because we have no guarantees the caller won't invalidate this slot.
+    /// The caller must ensure no EventProcessor is reading this slot and that no
+    /// other reference to it is alive.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut(&self, position: usize) -> &mut T {
+        &mut *self.entries()[position].get()
+    }
+
+    /// Overwrite the slot at `position` with `data`
+    ///
+    /// # Safety
+    ///
+    /// Unsafe because we have no guarantees the caller won't invalidate this slot.
+    /// The caller must ensure no EventProcessor is reading this slot.
     pub unsafe fn write(&self, position: usize, data: T) {
-        let v: *mut Vec<T> = self.entries.get();
-        let slot = (*v).get_mut(position);
-        drop(&*slot);
-        *slot = data;
+        *self.entries()[position].get() = data;
     }
 }
 
@@ -83,9 +283,10 @@ impl<T: Slot + Send> RingBuffer<T> {
 #[cfg(test)]
 mod tests {
 
-    use super::{RingBuffer, Slot};
+    use super::{RingBuffer, Slot, Indexer};
+    use crate::error::TurbineError;
 
-    #[deriving(Show)]
+    #[derive(Debug)]
     struct TestSlot;
 
     impl Slot for TestSlot {
@@ -96,18 +297,52 @@ mod tests {
 
     #[test]
     fn new_ringbuf() {
-        let _: RingBuffer<TestSlot> = RingBuffer::new(2);
+        let _: RingBuffer<TestSlot> = RingBuffer::new(2).unwrap();
+    }
+
+    #[test]
+    fn new_ringbuf_default() {
+        let r: RingBuffer<u64> = RingBuffer::new(4).unwrap();
+        assert!(unsafe { r.get(0, 4) } == [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn new_ringbuf_with_factory() {
+        let mut created = 0;
+        let r = RingBuffer::with_factory(4, || {
+            created += 1;
+            Vec::<u8>::with_capacity(4096)
+        }).unwrap();
+
+        assert!(created == 4);
+        assert!(unsafe { r.get(0, 4) }.iter().all(|v| v.is_empty() && v.capacity() >= 4096));
+        assert!(RingBuffer::with_factory(3, Vec::<u8>::new).err() == Some(TurbineError::InvalidSize(3)));
     }
 
     #[test]
-    #[should_fail]
     fn new_ringbuff_non_power_of_two() {
-        let _: RingBuffer<TestSlot> = RingBuffer::new(5);
+        let r: Result<RingBuffer<TestSlot>, TurbineError> = RingBuffer::new(5);
+        assert!(r.err() == Some(TurbineError::InvalidSize(5)));
     }
 
     #[test]
-    #[should_fail]
     fn new_ringbuff_zero() {
-        let _: RingBuffer<TestSlot> = RingBuffer::new(0);
+        let r: Result<RingBuffer<TestSlot>, TurbineError> = RingBuffer::new(0);
+        assert!(r.err() == Some(TurbineError::InvalidSize(0)));
+    }
+
+    #[test]
+    fn new_ringbuf_exact_capacity() {
+        let r = RingBuffer::with_exact_capacity(3, || 0u8).unwrap();
+        assert!(r.get_capacity() == 3);
+        assert!(r.indexer() == Indexer::Modulo(3));
+        assert!((0..7).map(|seq| r.indexer().position(seq)).collect::<Vec<_>>() == vec![0, 1, 2, 0, 1, 2, 0]);
+
+        // Power of two sizes keep the mask
+        let r = RingBuffer::with_exact_capacity(8, || 0u8).unwrap();
+        assert!(r.indexer() == Indexer::Mask(7));
+        assert!(r.indexer().position(13) == 5);
+
+        assert!(RingBuffer::with_exact_capacity(0, || 0u8).err() == Some(TurbineError::InvalidSize(0)));
     }
 }
@@ -0,0 +1,363 @@
+use std::cell::UnsafeCell;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem::{align_of, size_of};
+use std::path::Path;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use crate::error::TurbineError;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"TURBINE\0");
+const VERSION: u32 = 1;
+
+const BUILDING: u32 = 0;
+const FINALIZED: u32 = 1;
+
+/// Offsets into the segment are aligned to at least this, so every region starts
+/// on its own cache line
+const REGION_ALIGNMENT: usize = 128;
+
+fn align_up(offset: usize, alignment: usize) -> Option<usize> {
+    Some(offset.checked_add(alignment - 1)? & !(alignment - 1))
+}
+
+/// The fixed header at the start of every segment
+///
+/// The geometry fields are written once by the creator.  `end` and `poisoned` back
+/// the `Alert` of every process attached to the segment, so halting, shutting down
+/// or poisoning in one process is seen by all of them.
+#[repr(C)]
+pub(crate) struct Header {
+    magic: u64,
+    version: u32,
+    state: AtomicU32,
+    capacity: u64,
+    slot_size: u64,
+    slot_align: u64,
+    max_processors: u64,
+    processors: u64,
+    pub(crate) end: AtomicUsize,
+    pub(crate) poisoned: AtomicBool
+}
+
+/// Where each region lives in the segment
+///
+/// ```text
+/// | Header | cursors (max_processors + 1) | graph (max_processors rows) | ring |
+/// ```
+///
+/// Row `t` of the graph holds one byte per cursor, set if EP `t` depends on it.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    capacity: usize,
+    max_processors: usize,
+    cursors: usize,
+    graph: usize,
+    ring: usize,
+    len: usize
+}
+
+impl Layout {
+
+    /// Returns None if the segment would not fit in the address space, which for a
+    /// header read from a file means the header is garbage.
+    fn new<T>(capacity: usize, max_processors: usize) -> Option<Layout> {
+        let cursor_count = max_processors.checked_add(1)?;
+        let cursors = align_up(size_of::<Header>(), REGION_ALIGNMENT)?;
+        let graph = cursor_count.checked_mul(size_of::<AtomicUsize>())?.checked_add(cursors)?;
+        let graph_len = max_processors.checked_mul(cursor_count)?;
+        let ring = align_up(graph.checked_add(graph_len)?, REGION_ALIGNMENT.max(align_of::<T>()))?;
+
+        Some(Layout {
+            capacity,
+            max_processors,
+            cursors,
+            graph,
+            ring,
+            len: capacity.checked_mul(size_of::<T>())?.checked_add(ring)?
+        })
+    }
+}
+
+/// A file-backed shared memory segment holding a ring, its cursors and its graph
+///
+/// The creating process maps the file with `create` and owns the write side.  Other
+/// processes map the same file with `attach` once the graph has been finalized.  The
+/// mapping is released when the last RingBuffer, cursor set or Alert using it is
+/// dropped; the file itself is left in place for the caller to remove.
+pub(crate) struct Segment {
+    ptr: *mut u8,
+    layout: Layout
+}
+
+// The segment is only ever accessed through atomics, or through the RingBuffer,
+// whose slots are coordinated by the cursors exactly as for a heap allocation.
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Segment {
+
+    /// Create the file at `path` and lay out a segment for `capacity` slots of `T`
+    /// and up to `max_processors` EventProcessors.
+    ///
+    /// Every slot is initialised with `factory`.  The file must not exist yet: another
+    /// process may still have it mapped, and shrinking it under that process would
+    /// crash it with SIGBUS.
+    pub(crate) fn create<T: Copy>(path: &Path, capacity: usize, max_processors: usize,
+                                  mut factory: impl FnMut() -> T) -> Result<Segment, TurbineError> {
+        let layout = Layout::new::<T>(capacity, max_processors).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "segment does not fit in the address space")
+        })?;
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(path)
+            .map_err(|e| TurbineError::io_at(path, e))?;
+
+        // The file is extended with zeroes, which is a valid initial value for
+        // every cursor and for the graph.  If that fails, the file is removed again,
+        // since it would otherwise stop the path from ever being created
+        let mapped = file.set_len(layout.len as u64)
+            .map_err(|e| TurbineError::io_at(path, e))
+            .and_then(|_| map(&file, layout.len));
+        let segment = match mapped {
+            Ok(ptr) => Segment { ptr, layout },
+            Err(e) => {
+                let _ = fs::remove_file(path);
+                return Err(e);
+            }
+        };
+
+        // This is safe because nobody else can attach until the state is FINALIZED
+        unsafe {
+            std::ptr::write(segment.ptr as *mut Header, Header {
+                magic: MAGIC,
+                version: VERSION,
+                state: AtomicU32::new(BUILDING),
+                capacity: capacity as u64,
+                slot_size: size_of::<T>() as u64,
+                slot_align: align_of::<T>() as u64,
+                max_processors: max_processors as u64,
+                processors: 0,
+                end: AtomicUsize::new(usize::MAX),
+                poisoned: AtomicBool::new(false)
+            });
+            for slot in segment.ring::<T>() {
+                *slot.get() = factory();
+            }
+        }
+        Ok(segment)
+    }
+
+    /// Map an existing segment created for slots of `T`.
+    ///
+    /// Returns `TurbineError::IncompatibleSegment` if the file is not a segment, or was
+    /// created for a slot of a different size or alignment, and
+    /// `TurbineError::NotFinalized` if its graph has not been finalized yet.
+    pub(crate) fn attach<T: Copy>(path: &Path) -> Result<Segment, TurbineError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)
+            .map_err(|e| TurbineError::io_at(path, e))?;
+        let len = file.metadata()?.len() as usize;
+        if len < size_of::<Header>() {
+            return Err(TurbineError::IncompatibleSegment);
+        }
+
+        // The geometry is not known until the header has been read, so the whole
+        // file is mapped and checked against it
+        let mut segment = Segment {
+            ptr: map(&file, len)?,
+            layout: Layout { capacity: 0, max_processors: 0, cursors: 0, graph: 0, ring: 0, len }
+        };
+
+        let header = segment.header();
+        if header.magic != MAGIC || header.version != VERSION
+            || header.slot_size != size_of::<T>() as u64 || header.slot_align != align_of::<T>() as u64 {
+            return Err(TurbineError::IncompatibleSegment);
+        }
+        if header.state.load(Ordering::Acquire) != FINALIZED {
+            return Err(TurbineError::NotFinalized);
+        }
+
+        // The header is not trusted, so its geometry must fit in the address space as
+        // well as in the file
+        let layout = usize::try_from(header.capacity).ok()
+            .zip(usize::try_from(header.max_processors).ok())
+            .and_then(|(capacity, max_processors)| Layout::new::<T>(capacity, max_processors));
+        let layout = match layout {
+            Some(layout) if layout.len <= len => layout,
+            _ => return Err(TurbineError::IncompatibleSegment)
+        };
+        segment.layout = Layout { len, ..layout };
+        Ok(segment)
+    }
+
+    pub(crate) fn header(&self) -> &Header {
+        // This is safe because the segment is at least as long as the header, and
+        // the mapping is page aligned
+        unsafe { &*(self.ptr as *const Header) }
+    }
+
+    /// The number of EventProcessors the segment has room for
+    pub(crate) fn max_processors(&self) -> usize {
+        self.layout.max_processors
+    }
+
+    /// The number of EventProcessors in the finalized graph
+    pub(crate) fn processors(&self) -> usize {
+        self.header().processors as usize
+    }
+
+    /// All of the cursors in the segment, including the ones no EP uses
+    pub(crate) fn cursors(&self) -> &[AtomicUsize] {
+        // This is safe because the region is aligned for AtomicUsize, and a zeroed
+        // AtomicUsize is valid
+        unsafe {
+            slice::from_raw_parts(self.ptr.add(self.layout.cursors) as *const AtomicUsize, self.layout.max_processors + 1)
+        }
+    }
+
+    /// The slots of the ring
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type the segment was created or attached for.  Access to the
+    /// slots must be coordinated through the cursors, as for `RingBuffer`.
+    pub(crate) unsafe fn ring<T>(&self) -> &[UnsafeCell<T>] {
+        slice::from_raw_parts(self.ptr.add(self.layout.ring) as *const UnsafeCell<T>, self.layout.capacity)
+    }
+
+    /// Record the finalized dependency graph, allowing other processes to attach.
+    ///
+    /// `graph` is indexed by token and holds cursor indices, as built by
+    /// `Turbine::finalize_graph`.
+    pub(crate) fn finalize(&self, graph: &[Vec<usize>]) {
+        let row = self.layout.max_processors + 1;
+
+        // This is safe because only the creator writes the graph, and only before the
+        // state is FINALIZED, so nobody else can be reading it
+        unsafe {
+            let rows = slice::from_raw_parts_mut(self.ptr.add(self.layout.graph), self.layout.max_processors * row);
+            for (token, deps) in graph.iter().enumerate() {
+                for &dep in deps {
+                    rows[token * row + dep] = 1;
+                }
+            }
+            (*(self.ptr as *mut Header)).processors = graph.len() as u64;
+        }
+        self.header().state.store(FINALIZED, Ordering::Release);
+    }
+
+    /// Read the finalized dependency graph back, in the form `Segment::finalize` took it.
+    pub(crate) fn graph(&self) -> Vec<Vec<usize>> {
+        let row = self.layout.max_processors + 1;
+
+        // This is safe because the graph is never written again once finalized
+        let rows = unsafe {
+            slice::from_raw_parts(self.ptr.add(self.layout.graph), self.layout.max_processors * row)
+        };
+        (0..self.processors()).map(|token| {
+            (0..row).filter(|&dep| rows[token * row + dep] != 0).collect()
+        }).collect()
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        unmap(self.ptr, self.layout.len);
+    }
+}
+
+#[cfg(unix)]
+fn map(file: &File, len: usize) -> Result<*mut u8, TurbineError> {
+    use std::os::unix::io::AsRawFd;
+
+    // This is safe because the file is at least `len` bytes long, and the mapping
+    // is only released by `unmap`
+    let ptr = unsafe {
+        libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, file.as_raw_fd(), 0)
+    };
+    if ptr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(ptr as *mut u8)
+}
+
+#[cfg(unix)]
+fn unmap(ptr: *mut u8, len: usize) {
+    unsafe {
+        libc::munmap(ptr as *mut libc::c_void, len);
+    }
+}
+
+#[cfg(not(unix))]
+fn map(_: &File, _: usize) -> Result<*mut u8, TurbineError> {
+    Err(io::Error::from(io::ErrorKind::Unsupported).into())
+}
+
+#[cfg(not(unix))]
+fn unmap(_: *mut u8, _: usize) {}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Segment, Header};
+    use crate::TurbineError;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::mem::offset_of;
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn test_segment_round_trip() {
+        let path = env::temp_dir().join(format!("turbine-segment-{}", std::process::id()));
+        let segment = Segment::create::<u64>(&path, 16, 3, || 7).unwrap();
+        assert!(Segment::create::<u64>(&path, 16, 3, || 7).is_err());
+        assert!(unsafe { segment.ring::<u64>() }.iter().all(|slot| unsafe { *slot.get() } == 7));
+
+        assert!(Segment::attach::<u64>(&path).err() == Some(TurbineError::NotFinalized));
+        segment.finalize(&[vec![0], vec![1, 0]]);
+
+        assert!(Segment::attach::<u32>(&path).err() == Some(TurbineError::IncompatibleSegment));
+        let attached = Segment::attach::<u64>(&path).unwrap();
+        assert!(attached.graph() == vec![vec![0], vec![0, 1]]);
+        assert!(attached.cursors().len() == 4);
+
+        segment.cursors()[2].store(5, std::sync::atomic::Ordering::SeqCst);
+        assert!(attached.cursors()[2].load(std::sync::atomic::Ordering::SeqCst) == 5);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_attach_rejects_overflowing_header() {
+        let path = env::temp_dir().join(format!("turbine-segment-overflow-{}", std::process::id()));
+        let segment = Segment::create::<u64>(&path, 16, 1, || 0).unwrap();
+        segment.finalize(&[vec![0]]);
+
+        // A capacity which overflows the segment length must not wrap around to one
+        // which appears to fit in the file
+        let capacity = offset_of!(Header, capacity) as u64;
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&(u64::MAX / 8 + 2).to_ne_bytes(), capacity).unwrap();
+        assert!(Segment::attach::<u64>(&path).err() == Some(TurbineError::IncompatibleSegment));
+
+        file.write_all_at(&u64::MAX.to_ne_bytes(), capacity).unwrap();
+        assert!(Segment::attach::<u64>(&path).err() == Some(TurbineError::IncompatibleSegment));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_failed_create_removes_file() {
+        let path = env::temp_dir().join(format!("turbine-segment-huge-{}", std::process::id()));
+
+        // The file can't be extended past the largest file offset
+        assert!(Segment::create::<u64>(&path, 1 << 60, 1, || 0).is_err());
+        assert!(!path.exists());
+
+        // Nor can a segment larger than the address space be laid out at all
+        assert!(Segment::create::<u64>(&path, 1 << 61, 1, || 0).is_err());
+        assert!(!path.exists());
+
+        let segment = Segment::create::<u64>(&path, 16, 1, || 0);
+        assert!(segment.is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,14 +1,22 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Condvar};
 use std::cmp::{min};
+use std::hint;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+use crate::error::Timeout;
+use crate::control::Alert;
 
 /// A trait which provides a unified interface to various waiting strategies
+///
+/// `EventProcessor::start::<W>` instantiates the strategy with `new`, which must
+/// provide a sensible default configuration.  Strategies with tunable parameters
+/// can also be built by hand and passed to `EventProcessor::start_with`.
 pub trait WaitStrategy {
 
     /// Instantiate a new WaitStrategy. Must provide the size of the underlying buffer
-    fn new(ring_size: usize) -> Self;
-
-    /// Get the underlying max buffer capacity
-    fn get_ring_size(&self) -> usize;
+    /// and the Notifier which is signalled whenever a cursor advances.
+    fn new(ring_size: usize, notifier: Arc<Notifier>) -> Self;
 
     /// Wait for the requested sequence, but return the largest available
     ///
@@ -17,63 +25,695 @@ pub trait WaitStrategy {
     /// of blocking varies depending on the implementation (e.g. busy-wait, sleep, etc).
     ///
     /// This method should return the highest available position in the buffer to
-    /// allow EventProcessors to batch reads
-    fn wait_for(&self, sequence: u64, ep: &Vec<&AtomicUsize>) -> u64;
+    /// allow EventProcessors to batch reads.
+    ///
+    /// If there is no new data and `alert` reports that the stream has ended at
+    /// `sequence` (e.g. the Turbine was halted or shut down), the wait is abandoned
+    /// and `sequence` itself is returned, signalling that there is nothing to read.
+    fn wait_for(&self, sequence: u64, ep: &[&AtomicUsize], alert: &Alert) -> u64;
+
+    /// Wait for the requested sequence, giving up after `timeout`
+    ///
+    /// Behaves like `wait_for`, but returns `Err(Timeout)` if no new data became
+    /// available before the timeout elapsed.  This allows EventProcessors to wake
+    /// up periodically during quiet periods.
+    ///
+    /// The default implementation busy-spins until the deadline.
+    fn wait_for_timeout(&self, sequence: u64, ep: &[&AtomicUsize], alert: &Alert, timeout: Duration) -> Result<u64, Timeout> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(v) = can_read(sequence, ep, alert) {
+                return Ok(v);
+            }
+            if Instant::now() >= deadline {
+                return Err(Timeout);
+            }
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Wakes EventProcessors which are blocked waiting for data
+///
+/// A single Notifier is shared by a Turbine, its producers and all of its
+/// EventProcessors.  Every time a cursor advances (a write is published, or an EP
+/// finishes a batch) `notify_all` is called.  Blocking WaitStrategies park on the
+/// Notifier's condition variable instead of spinning.
+///
+/// The Notifier keeps a count of blocked waiters, so when no EventProcessor is
+/// blocked `notify_all` is a single atomic load and never touches the mutex.
+///
+/// Producers waiting with `ParkingWait` register their thread with the Notifier
+/// as well, so that an EP finishing a batch unparks them.  While any producer is
+/// registered, `notify_all` also takes a lock to unpark it.  A producer is only
+/// registered for as long as it is waiting for a slot.
+pub struct Notifier {
+    lock: Mutex<()>,
+    condvar: Condvar,
+    waiters: AtomicUsize,
+    parked: Mutex<Vec<Thread>>,
+    parked_count: AtomicUsize
+}
+
+impl Notifier {
+
+    pub fn new() -> Notifier {
+        Notifier {
+            lock: Mutex::new(()),
+            condvar: Condvar::new(),
+            waiters: AtomicUsize::new(0),
+            parked: Mutex::new(vec![]),
+            parked_count: AtomicUsize::new(0)
+        }
+    }
+
+    /// Wake every thread blocked in `wait_until`
+    ///
+    /// Must be called *after* the cursor has been stored, so that a woken waiter
+    /// observes the new position.
+    pub fn notify_all(&self) {
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.condvar.notify_all();
+        }
+        if self.parked_count.load(Ordering::SeqCst) > 0 {
+            for thread in self.parked.lock().unwrap().iter() {
+                thread.unpark();
+            }
+        }
+    }
+
+    /// Park the current thread until the next `notify_all`, or `timeout` elapses
+    ///
+    /// A thread stays registered until `unregister`, so every `notify_all` in between
+    /// unparks it, even while it is running.  The unpark is then remembered by the
+    /// thread's park token, which means a cursor advancing between the caller's
+    /// check and the park is never missed.  The first call of a wait only registers
+    /// the thread and returns, for the same reason.  Spurious wakeups are possible.
+    pub(crate) fn park_timeout(&self, timeout: Duration) {
+        let current = thread::current();
+        {
+            let mut parked = self.parked.lock().unwrap();
+            if !parked.iter().any(|t| t.id() == current.id()) {
+                parked.push(current);
+                self.parked_count.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+        }
+        thread::park_timeout(timeout);
+    }
+
+    /// Stop unparking the current thread, once it is done waiting
+    pub(crate) fn unregister(&self) {
+        if self.parked_count.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        let current = thread::current().id();
+        let mut parked = self.parked.lock().unwrap();
+        if let Some(i) = parked.iter().position(|t| t.id() == current) {
+            parked.swap_remove(i);
+            self.parked_count.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// The number of threads registered by `park_timeout`
+    #[cfg(test)]
+    pub(crate) fn parked(&self) -> usize {
+        self.parked_count.load(Ordering::SeqCst)
+    }
+
+    /// Block until `ready` returns a value
+    ///
+    /// `ready` is re-evaluated every time the Notifier is signalled.  It is checked
+    /// once before taking the lock, so a waiter never blocks if data is already
+    /// available.
+    pub fn wait_until<R, F>(&self, mut ready: F) -> R where F: FnMut() -> Option<R> {
+        if let Some(v) = ready() {
+            return v;
+        }
+
+        let mut guard = self.lock.lock().unwrap();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+
+        // Registering as a waiter before re-checking means a concurrent
+        // `notify_all` either sees us waiting, or we see its cursor update
+        let result = loop {
+            if let Some(v) = ready() {
+                break v;
+            }
+            guard = self.condvar.wait(guard).unwrap();
+        };
+
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    /// Block until `ready` returns a value, or `timeout` elapses
+    ///
+    /// Returns None if the timeout elapsed first.
+    pub fn wait_until_timeout<R, F>(&self, mut ready: F, timeout: Duration) -> Option<R> where F: FnMut() -> Option<R> {
+        if let Some(v) = ready() {
+            return Some(v);
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.lock.lock().unwrap();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+
+        let result = loop {
+            if let Some(v) = ready() {
+                break Some(v);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                break None;
+            }
+            guard = self.condvar.wait_timeout(guard, deadline - now).unwrap().0;
+        };
+
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Notifier {
+        Notifier::new()
+    }
+}
+
+/// Find the highest sequence available in every dependency
+///
+/// Returns None if any dependency has not yet passed `sequence`, e.g. there is
+/// nothing new to read.  EPs are never ahead of their dependencies, but a
+/// WorkerPool worker may have claimed a sequence which is not yet published.
+/// If the stream has ended at `sequence`, there never will be anything, so
+/// `sequence` itself is returned to stop the wait.
+fn can_read(sequence: u64, deps: &[&AtomicUsize], alert: &Alert) -> Option<u64> {
+    let mut min_cursor = u64::MAX;
+
+    for v in deps.iter() {
+        let cursor = v.load(Ordering::SeqCst) as u64;
+        debug!("					cursor: {}", cursor);
+
+        if cursor <= sequence {
+            debug!("					Not past dep cursor, abort!");
+            // at (or ahead of) a dependency. we can't move
+            return if alert.is_ended(sequence) { Some(sequence) } else { None };
+        }
+        min_cursor = min(min_cursor, cursor);
+        debug!("					dep cursor: {}, sequence: {}", cursor, sequence);
+        debug!("					min_cursor: {}", min_cursor);
+
+    }
+    Some(min_cursor)
+}
+
+/// A trait which controls how a producer waits for a free slot in the buffer
+///
+/// When every slot in the buffer is still in use by an EventProcessor, the producer
+/// must wait for the slowest EP to catch up.  The producer repeatedly checks for a
+/// free slot and calls `wait` after each failed attempt, so the strategy decides how
+/// much CPU a backpressured producer burns.
+pub trait ProducerWaitStrategy {
+
+    /// Back off after a failed attempt to find a free slot.
+    ///
+    /// `attempt` counts the consecutive failed attempts for the current write,
+    /// starting at zero, which allows strategies to escalate the longer they wait.
+    fn wait(&self, attempt: u64);
+
+    /// Back off after a failed attempt, with access to the Notifier which is
+    /// signalled whenever an EventProcessor advances.
+    ///
+    /// This is what the writer actually calls.  It defaults to `wait`; strategies
+    /// which block override it so that they are woken as soon as a slot is released.
+    fn wait_notified(&self, attempt: u64, _notifier: &Notifier) {
+        self.wait(attempt)
+    }
+}
+
+/// The writer's wait for one free slot
+///
+/// Counts the attempts passed to the ProducerWaitStrategy.  A strategy such as
+/// `ParkingWait` may register the thread with the Notifier while it waits, so the
+/// registration is dropped along with the WriterWait.
+pub(crate) struct WriterWait {
+    attempt: u64,
+    notifier: Option<Arc<Notifier>>
+}
+
+impl WriterWait {
+
+    pub(crate) fn new() -> WriterWait {
+        WriterWait {
+            attempt: 0,
+            notifier: None
+        }
+    }
+
+    /// Back off after a failed attempt to find a free slot
+    pub(crate) fn wait<P: ProducerWaitStrategy>(&mut self, wait_strategy: &P, notifier: &Arc<Notifier>) {
+        if self.notifier.is_none() {
+            self.notifier = Some(notifier.clone());
+        }
+        wait_strategy.wait_notified(self.attempt, notifier);
+        self.attempt += 1;
+    }
+}
+
+impl Drop for WriterWait {
+    fn drop(&mut self) {
+        if let Some(ref notifier) = self.notifier {
+            notifier.unregister();
+        }
+    }
 }
 
 /// An implementation of WaitStrategy that busy-spins while waiting
 ///
 /// This strategy should have the best perforamnce and keep caches hot, but will chew
 /// CPU while there is no work to be done.
+///
+/// As a ProducerWaitStrategy, it busy-spins until a slot is free.  This is the
+/// default producer strategy.
+///
+/// `BusyWait::default()` creates one for use as a ProducerWaitStrategy, which has
+/// no ring size.
+#[derive(Clone, Default)]
 pub struct BusyWait {
     ring_size: usize
 }
 
 impl BusyWait {
-    fn can_read(&self, sequence: u64, deps: &Vec<&AtomicUsize>) -> Option<u64> {
-        let mut min_cursor = 18446744073709551615;
-
-        for v in deps.iter() {
-            let cursor = v.load(Ordering::SeqCst) as u64;
-            debug!("					cursor: {}", cursor);
-
-            if sequence == cursor {
-                debug!("					Same as dep cursor, abort!");
-                return None;	// at same position as a dependency. we can't move
-            }
-            min_cursor = min(min_cursor, cursor);
-            debug!("					dep cursor: {}, ring_size: {}, sequence: {}", cursor, self.ring_size as isize, sequence);
-            debug!("					min_cursor: {}", min_cursor);
 
-        }
-        Some(min_cursor)
+    /// Get the underlying max buffer capacity
+    pub fn get_ring_size(&self) -> usize {
+        self.ring_size
     }
 }
 
 impl WaitStrategy for BusyWait {
-    fn new(ring_size: usize) -> BusyWait {
+    fn new(ring_size: usize, _notifier: Arc<Notifier>) -> BusyWait {
         BusyWait {
-            ring_size: ring_size
+            ring_size
         }
     }
 
-    fn get_ring_size(&self) -> usize {
-        self.ring_size
-    }
-
-    fn wait_for(&self, sequence: u64, deps: &Vec<&AtomicUsize>) -> u64 {
-        let mut available: u64 = 0;
+    fn wait_for(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert) -> u64 {
+        let available: u64;
         debug!("					Waiting for: {}", sequence);
         loop {
-            match self.can_read(sequence, deps) {
-                Some(v) => {
-                    available = v;
-                    break
-                },
-                None => {}
+            if let Some(v) = can_read(sequence, deps, alert) {
+                available = v;
+                break
             }
         }
         debug!("					Wait done, returning {}", available);
         available
     }
 }
+
+/// An implementation of WaitStrategy that blocks on a condition variable while waiting
+///
+/// EventProcessors using this strategy sleep on the shared Notifier and are woken
+/// whenever a write is published or another EP advances.  An idle EP therefore
+/// costs no CPU, at the price of higher latency than `BusyWait` when data arrives.
+///
+/// This is the equivalent of the Disruptor's BlockingWaitStrategy.
+pub struct BlockingWait {
+    ring_size: usize,
+    notifier: Arc<Notifier>
+}
+
+impl BlockingWait {
+
+    /// Get the underlying max buffer capacity
+    pub fn get_ring_size(&self) -> usize {
+        self.ring_size
+    }
+}
+
+impl WaitStrategy for BlockingWait {
+    fn new(ring_size: usize, notifier: Arc<Notifier>) -> BlockingWait {
+        BlockingWait {
+            ring_size,
+            notifier
+        }
+    }
+
+    fn wait_for(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert) -> u64 {
+        debug!("					Blocking for: {}", sequence);
+        let available = self.notifier.wait_until(|| can_read(sequence, deps, alert));
+        debug!("					Wait done, returning {}", available);
+        available
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert, timeout: Duration) -> Result<u64, Timeout> {
+        debug!("					Blocking for: {} (timeout {:?})", sequence, timeout);
+        self.notifier.wait_until_timeout(|| can_read(sequence, deps, alert), timeout).ok_or(Timeout)
+    }
+}
+
+impl ProducerWaitStrategy for BusyWait {
+    fn wait(&self, _attempt: u64) {
+        hint::spin_loop();
+    }
+}
+
+/// Poll the dependencies, backing off with `strategy` between each failed attempt
+fn backoff_until<S: ProducerWaitStrategy>(strategy: &S, sequence: u64, deps: &[&AtomicUsize], alert: &Alert) -> u64 {
+    let mut attempt = 0;
+    loop {
+        if let Some(v) = can_read(sequence, deps, alert) {
+            return v;
+        }
+        strategy.wait(attempt);
+        attempt += 1;
+    }
+}
+
+/// Like `backoff_until`, but gives up once `timeout` has elapsed
+fn backoff_until_timeout<S: ProducerWaitStrategy>(strategy: &S, sequence: u64, deps: &[&AtomicUsize], alert: &Alert, timeout: Duration) -> Result<u64, Timeout> {
+    let deadline = Instant::now() + timeout;
+    let mut attempt = 0;
+    loop {
+        if let Some(v) = can_read(sequence, deps, alert) {
+            return Ok(v);
+        }
+        if Instant::now() >= deadline {
+            return Err(Timeout);
+        }
+        strategy.wait(attempt);
+        attempt += 1;
+    }
+}
+
+/// A wait strategy that spins for a while, then yields its timeslice
+///
+/// After `spin_tries` failed attempts the thread calls `thread::yield_now()`
+/// between each check, allowing other threads (such as the EventProcessor it is
+/// waiting on) to run on the same core.
+///
+/// May be used by both producers and EventProcessors.  `WaitStrategy::new`
+/// spins 100 times before yielding.
+#[derive(Clone)]
+pub struct YieldingWait {
+    spin_tries: u64
+}
+
+impl YieldingWait {
+
+    /// Create a YieldingWait which spins `spin_tries` times before yielding
+    pub fn new(spin_tries: u64) -> YieldingWait {
+        YieldingWait {
+            spin_tries
+        }
+    }
+}
+
+impl Default for YieldingWait {
+    fn default() -> YieldingWait {
+        YieldingWait::new(100)
+    }
+}
+
+impl ProducerWaitStrategy for YieldingWait {
+    fn wait(&self, attempt: u64) {
+        if attempt < self.spin_tries {
+            hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+impl WaitStrategy for YieldingWait {
+    fn new(_ring_size: usize, _notifier: Arc<Notifier>) -> YieldingWait {
+        YieldingWait::default()
+    }
+
+    fn wait_for(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert) -> u64 {
+        debug!("					Yielding for: {}", sequence);
+        backoff_until(self, sequence, deps, alert)
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert, timeout: Duration) -> Result<u64, Timeout> {
+        backoff_until_timeout(self, sequence, deps, alert, timeout)
+    }
+}
+
+/// A ProducerWaitStrategy that parks the thread until an EventProcessor advances
+///
+/// The writer registers its thread with the Turbine's Notifier and parks, and is
+/// unparked as soon as an EP finishes a batch and releases slots.  Each park is
+/// also bounded by `timeout`, as a safety net.
+///
+/// Calling `wait` directly, without a Notifier, just parks for `timeout`.
+#[derive(Clone)]
+pub struct ParkingWait {
+    timeout: Duration
+}
+
+impl ParkingWait {
+
+    /// Create a ParkingWait which parks for at most `timeout` between attempts
+    pub fn new(timeout: Duration) -> ParkingWait {
+        ParkingWait {
+            timeout
+        }
+    }
+}
+
+impl Default for ParkingWait {
+    fn default() -> ParkingWait {
+        ParkingWait::new(Duration::from_micros(50))
+    }
+}
+
+impl ProducerWaitStrategy for ParkingWait {
+    fn wait(&self, _attempt: u64) {
+        thread::park_timeout(self.timeout);
+    }
+
+    fn wait_notified(&self, _attempt: u64, notifier: &Notifier) {
+        notifier.park_timeout(self.timeout);
+    }
+}
+
+/// A wait strategy that escalates from spinning, to yielding, to sleeping
+///
+/// The first `spin_tries` attempts busy-spin, the next `yield_tries` attempts
+/// yield the timeslice and every attempt after that sleeps for `sleep_nanos`.
+/// This keeps latency low for short stalls while a long stall costs almost no CPU.
+///
+/// May be used by both producers and EventProcessors.  `WaitStrategy::new`
+/// spins 100 times, yields 100 times and then sleeps for 100ns at a time.
+#[derive(Clone)]
+pub struct SleepingWait {
+    spin_tries: u64,
+    yield_tries: u64,
+    sleep: Duration
+}
+
+impl SleepingWait {
+
+    /// Create a SleepingWait which sleeps for `sleep_nanos` once spinning and
+    /// yielding have been exhausted
+    pub fn new(sleep_nanos: u64) -> SleepingWait {
+        SleepingWait {
+            spin_tries: 100,
+            yield_tries: 100,
+            sleep: Duration::from_nanos(sleep_nanos)
+        }
+    }
+
+    /// Set how many attempts spin, and then yield, before sleeping
+    pub fn with_tries(mut self, spin_tries: u64, yield_tries: u64) -> SleepingWait {
+        self.spin_tries = spin_tries;
+        self.yield_tries = yield_tries;
+        self
+    }
+}
+
+impl Default for SleepingWait {
+    fn default() -> SleepingWait {
+        SleepingWait::new(100)
+    }
+}
+
+impl ProducerWaitStrategy for SleepingWait {
+    fn wait(&self, attempt: u64) {
+        if attempt < self.spin_tries {
+            hint::spin_loop();
+        } else if attempt < self.spin_tries + self.yield_tries {
+            thread::yield_now();
+        } else {
+            thread::sleep(self.sleep);
+        }
+    }
+}
+
+impl WaitStrategy for SleepingWait {
+    fn new(_ring_size: usize, _notifier: Arc<Notifier>) -> SleepingWait {
+        SleepingWait::default()
+    }
+
+    fn wait_for(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert) -> u64 {
+        debug!("					Sleeping for: {}", sequence);
+        backoff_until(self, sequence, deps, alert)
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert, timeout: Duration) -> Result<u64, Timeout> {
+        backoff_until_timeout(self, sequence, deps, alert, timeout)
+    }
+}
+
+/// A WaitStrategy that escalates from spinning, to yielding, to blocking
+///
+/// The first `spin_tries` attempts busy-spin and the next `yield_tries` attempts
+/// yield the timeslice.  If data still has not arrived, the EventProcessor blocks
+/// on the Notifier's condition variable like `BlockingWait`.  Bursty pipelines
+/// get spin-level latency while busy, yet cost no CPU once they go quiet.
+///
+/// `WaitStrategy::new` spins 10,000 times and yields 100 times before blocking.
+/// Use `with_tries` to configure the thresholds and pass the result to
+/// `EventProcessor::start_with`.
+pub struct PhasedBackoffWait {
+    spin_tries: u64,
+    yield_tries: u64,
+    notifier: Arc<Notifier>
+}
+
+impl PhasedBackoffWait {
+
+    /// Set how many attempts spin, and then yield, before blocking
+    pub fn with_tries(mut self, spin_tries: u64, yield_tries: u64) -> PhasedBackoffWait {
+        self.spin_tries = spin_tries;
+        self.yield_tries = yield_tries;
+        self
+    }
+
+    /// Run the spin and yield phases, stopping early if `deadline` passes
+    ///
+    /// Returns None if data did not arrive and the EP should block.
+    fn back_off(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert, deadline: Option<Instant>) -> Option<u64> {
+        let mut attempt = 0;
+        while attempt < self.spin_tries + self.yield_tries {
+            if let Some(v) = can_read(sequence, deps, alert) {
+                return Some(v);
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return None;
+            }
+
+            if attempt < self.spin_tries {
+                hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+            attempt += 1;
+        }
+        None
+    }
+}
+
+impl WaitStrategy for PhasedBackoffWait {
+    fn new(_ring_size: usize, notifier: Arc<Notifier>) -> PhasedBackoffWait {
+        PhasedBackoffWait {
+            spin_tries: 10000,
+            yield_tries: 100,
+            notifier
+        }
+    }
+
+    fn wait_for(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert) -> u64 {
+        debug!("					Backing off for: {}", sequence);
+        if let Some(v) = self.back_off(sequence, deps, alert, None) {
+            return v;
+        }
+
+        debug!("					Blocking for: {}", sequence);
+        self.notifier.wait_until(|| can_read(sequence, deps, alert))
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &[&AtomicUsize], alert: &Alert, timeout: Duration) -> Result<u64, Timeout> {
+        let deadline = Instant::now() + timeout;
+        if let Some(v) = self.back_off(sequence, deps, alert, Some(deadline)) {
+            return Ok(v);
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Timeout);
+        }
+        self.notifier.wait_until_timeout(|| can_read(sequence, deps, alert), deadline - now).ok_or(Timeout)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Notifier, WaitStrategy, ProducerWaitStrategy, BlockingWait, SleepingWait};
+    use crate::control::Alert;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_blocking_wakes_on_notify() {
+        let notifier = Arc::new(Notifier::new());
+        let alert = Arc::new(Alert::new(notifier.clone()));
+        let cursor = Arc::new(AtomicUsize::new(0));
+
+        let (n, a, c) = (notifier.clone(), alert.clone(), cursor.clone());
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let strategy = BlockingWait::new(16, n);
+            let available = strategy.wait_for_timeout(0, &[&c], &a, Duration::from_secs(10));
+            (available, start.elapsed())
+        });
+
+        // Once the EP is blocked, a cursor advancing goes unnoticed until it is notified
+        while notifier.waiters.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+        cursor.store(3, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        assert!(!handle.is_finished());
+
+        notifier.notify_all();
+        let (available, elapsed) = handle.join().unwrap();
+        assert!(available == Ok(3));
+        assert!(elapsed < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_sleeping_backoff_bounds() {
+        let sleep = Duration::from_millis(20);
+        let strategy = SleepingWait::new(sleep.as_nanos() as u64).with_tries(2, 2);
+
+        // Spinning and yielding never sleep, but every attempt after them does
+        let start = Instant::now();
+        for attempt in 0..4 {
+            strategy.wait(attempt);
+        }
+        assert!(start.elapsed() < sleep);
+        let start = Instant::now();
+        strategy.wait(4);
+        assert!(start.elapsed() >= sleep);
+
+        // A timed wait gives up once its timeout has passed, within about one sleep
+        let alert = Alert::new(Arc::new(Notifier::new()));
+        let cursor = AtomicUsize::new(0);
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+        assert!(strategy.wait_for_timeout(0, &[&cursor], &alert, timeout).is_err());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= timeout && elapsed < timeout + sleep * 10);
+    }
+}
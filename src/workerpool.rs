@@ -0,0 +1,428 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::waitstrategy::{WaitStrategy, Notifier};
+use crate::control::Alert;
+use crate::exceptionhandler::{ExceptionHandler, BoxedExceptionHandler};
+use crate::cursors::Cursors;
+use crate::error::TurbineError;
+use crate::producer::AvailableSequences;
+use crate::ringbuffer::{RingBuffer, Indexer};
+
+/// Shared state of the workers in one WorkerPool
+///
+/// `work` hands out the next sequence to process.  Workers finish their sequences
+/// out of order, so the pool's cursor is advanced through `AvailableSequences`.
+pub(crate) struct WorkQueue {
+    work: AtomicUsize,
+    completed: AvailableSequences
+}
+
+impl WorkQueue {
+
+    pub(crate) fn new(indexer: Indexer, start: u64) -> WorkQueue {
+        WorkQueue {
+            work: AtomicUsize::new(start as usize),
+            completed: AvailableSequences::new(indexer)
+        }
+    }
+}
+
+/// A stage of competing consumers, where each event is handled by exactly one worker
+///
+/// Every EventProcessor sees every event.  A WorkerPool instead takes the place of
+/// a single EP in the dependency graph, and shares the events between any number of
+/// workers.  Each worker claims the next sequence from an atomic work cursor, so
+/// events are spread across whichever workers are free.
+///
+/// The pool has a single cursor, which only advances once every sequence before it
+/// has been processed by some worker.  EPs which depend on the pool, and the writer,
+/// are gated on that combined progress exactly as they would be on a single EP.
+///
+/// A WorkerPool is obtained from `Turbine::worker_pool`.  Clones of it, or further
+/// calls to `worker_pool` with the same token, join the same pool; each one is
+/// usually moved to its own thread and started there.
+///
+/// ## Example
+///
+///```
+///# use turbine::{Turbine, Slot, BlockingWait, TurbineError};
+///# use std::thread;
+///# struct TestSlot { value: isize }
+///# impl Slot for TestSlot { fn new() -> TestSlot { TestSlot { value: -1 } } }
+///let mut t: Turbine<TestSlot> = Turbine::new(1024).unwrap();
+///let work = t.ep_new().unwrap();
+///let pool = t.worker_pool(work).unwrap();
+///
+///let workers: Vec<_> = (0..4).map(|_| {
+///    let worker = pool.clone();
+///    thread::spawn(move || {
+///        let mut handled = 0;
+///        worker.start::<BlockingWait>(|event: &TestSlot, sequence: u64| -> Result<(), TurbineError> {
+///            assert!(event.value as u64 == sequence);
+///            handled += 1;
+///            Ok(())
+///        });
+///        handled
+///    })
+///}).collect();
+///
+///for i in 0..100 {
+///    t.write(TestSlot { value: i }).unwrap();
+///}
+///drop(t);
+///
+///let total: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+///assert!(total == 100);
+///```
+pub struct WorkerPool<T> {
+    graph: Arc<Vec<Vec<usize>>>,
+    cursors: Arc<Cursors>,
+    token: usize,
+    ring: Arc<RingBuffer<T>>,
+    notifier: Arc<Notifier>,
+    alert: Arc<Alert>,
+    queue: Arc<WorkQueue>,
+    exception_handler: Option<Arc<Mutex<BoxedExceptionHandler<T>>>>
+}
+
+impl<T: Send> WorkerPool<T> {
+
+    /// Instantiate a new WorkerPool handle.
+    ///
+    /// This is for internal use only.  The parameters mirror `EventProcessor::new`,
+    /// plus the `queue` shared by every worker of the pool at `token`.
+    pub(crate) fn new(ring: Arc<RingBuffer<T>>, graph: Arc<Vec<Vec<usize>>>, cursors: Arc<Cursors>, notifier: Arc<Notifier>,
+                      alert: Arc<Alert>, token: usize, queue: Arc<WorkQueue>) -> WorkerPool<T> {
+        WorkerPool::<T> {
+            graph,
+            cursors,
+            token,
+            ring,
+            notifier,
+            alert,
+            queue,
+            exception_handler: None
+        }
+    }
+
+    /// Catch panics from the workers' closures and pass them to `handler`.
+    ///
+    /// The handler is shared by this handle and its later clones.  Workers are guarded
+    /// per event, so the handler sees exactly the sequence and slot which failed.
+    /// `Skip` completes the sequence and carries on, `Halt` stops the worker and
+    /// `Poison` stops every EP and worker.  After `Halt` the failed sequence is never
+    /// completed, so the pool's cursor, and everything gated on it, stops before it.
+    /// See `ExceptionHandler`.
+    pub fn with_exception_handler(mut self, handler: impl ExceptionHandler<T> + Send + 'static) -> WorkerPool<T> {
+        let handler: BoxedExceptionHandler<T> = Box::new(handler);
+        self.exception_handler = Some(Arc::new(Mutex::new(handler)));
+        self
+    }
+
+    /// Begin processing events as one worker of the pool.
+    ///
+    /// The closure is called with each event this worker claims, and its sequence.
+    /// Like `EventProcessor::start`, returning Err(TurbineError::Shutdown) stops this
+    /// worker, but the other workers keep going.  Workers also stop when the Turbine
+    /// is halted or shut down.
+    ///
+    /// Without an ExceptionHandler a panic unwinds out of the worker and its sequence
+    /// is never completed, which stalls the pool; see `with_exception_handler`.
+    pub fn start<W: WaitStrategy>(&self, f: impl FnMut(&T, u64) -> Result<(), TurbineError>) {
+        self.start_with(self.wait_strategy::<W>(), f)
+    }
+
+    /// Instantiate WaitStrategy `W` for this worker with its default configuration.
+    pub fn wait_strategy<W: WaitStrategy>(&self) -> W {
+        WaitStrategy::new(self.ring.get_capacity(), self.notifier.clone())
+    }
+
+    /// Begin processing events as one worker of the pool, using a pre-configured WaitStrategy.
+    ///
+    /// This behaves exactly like `start`, but the supplied WaitStrategy instance is used.
+    pub fn start_with<W: WaitStrategy>(&self, wait_strategy: W, mut f: impl FnMut(&T, u64) -> Result<(), TurbineError>) {
+        let indexer = self.ring.indexer();
+
+        let deps: Vec<&AtomicUsize> = self.graph[self.token].iter()
+            .map(|&dep| &self.cursors[dep])
+            .collect();
+        let cursor = &self.cursors[self.token + 1];
+
+        // The highest sequence known to be published by our dependencies, so most
+        // claims don't need to consult the WaitStrategy at all
+        let mut available = 0;
+
+        loop {
+            let sequence = self.queue.work.fetch_add(1, Ordering::SeqCst) as u64;
+
+            // Once the stream has ended the claimed sequence is simply abandoned,
+            // since nobody downstream will wait for it
+            if self.alert.is_ended(sequence) {
+                debug!("Worker: stream ended, stopping @ {}", sequence);
+                break;
+            }
+
+            if sequence >= available {
+                available = wait_strategy.wait_for(sequence, &deps, &self.alert);
+                if available == sequence {
+                    debug!("Worker: stream ended while waiting for {}", sequence);
+                    break;
+                }
+            }
+
+            let index = indexer.position(sequence);
+
+            // This is safe because the pool's cursor cannot move past `sequence` until
+            // it has been completed below, so the writer cannot reuse the slot.  Only
+            // this worker claimed `sequence`, and the slice is dropped before completing it
+            let status = {
+                let data: &[T] = unsafe { self.ring.get(index, index + 1) };
+                match panic::catch_unwind(AssertUnwindSafe(|| f(&data[0], sequence))) {
+                    Ok(status) => status,
+                    Err(payload) => {
+                        if self.on_panic(payload, sequence, &data[0]).is_err() {
+                            // The failed sequence is left incomplete, so nothing gated
+                            // on the pool processes it
+                            break;
+                        }
+                        Ok(())
+                    }
+                }
+            };
+
+            self.queue.completed.publish(sequence, cursor);
+            self.notifier.notify_all();
+
+            if status.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl<T> WorkerPool<T> {
+
+    /// Ask the ExceptionHandler what to do about a panic while processing `sequence`
+    ///
+    /// Without an ExceptionHandler the panic carries on unwinding.
+    fn on_panic(&self, payload: Box<dyn Any + Send>, sequence: u64, slot: &T) -> Result<(), TurbineError> {
+        let handler = match self.exception_handler {
+            Some(ref handler) => handler,
+            None => panic::resume_unwind(payload)
+        };
+
+        // A handler which panicked in another worker leaves the Mutex poisoned, but
+        // the handler itself is still usable
+        let action = handler.lock().unwrap_or_else(|e| e.into_inner()).on_panic(&*payload, sequence, slot);
+        action.apply(&self.alert, sequence)
+    }
+}
+
+impl<T> Clone for WorkerPool<T> {
+    fn clone(&self) -> WorkerPool<T> {
+        WorkerPool::<T> {
+            graph: self.graph.clone(),
+            cursors: self.cursors.clone(),
+            token: self.token,
+            ring: self.ring.clone(),
+            notifier: self.notifier.clone(),
+            alert: self.alert.clone(),
+            queue: self.queue.clone(),
+            exception_handler: self.exception_handler.clone()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{Turbine, Slot, YieldingWait, BusyWait, TurbineError, ExceptionAction};
+    use std::any::Any;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    struct TestSlot {
+        pub value: u64
+    }
+
+    impl Slot for TestSlot {
+        fn new() -> TestSlot {
+            TestSlot { value: 0 }
+        }
+    }
+
+    #[test]
+    fn test_worker_pool_handles_each_event_once() {
+        let count = 2000;
+        let handled = Arc::new((0..count).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>());
+
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+        let work = t.ep_new().unwrap();
+        let after = t.ep_new().unwrap();
+        t.ep_depends(after, work).unwrap();
+
+        let pool = t.worker_pool(work).unwrap();
+        let workers: Vec<_> = (0..3).map(|_| {
+            let worker = pool.clone();
+            let handled = handled.clone();
+            thread::spawn(move || {
+                worker.start_with(YieldingWait::new(10), |event: &TestSlot, sequence: u64| -> Result<(), TurbineError> {
+                    assert!(event.value == sequence);
+                    handled[sequence as usize].fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                });
+            })
+        }).collect();
+
+        // The dependent EP only sees events once some worker has finished them
+        let event_processor = t.ep_finalize(after).unwrap();
+        let downstream = {
+            let handled = handled.clone();
+            thread::spawn(move || {
+                let mut seen = 0;
+                event_processor.start_with(YieldingWait::new(10), |data: &[TestSlot]| -> Result<(), TurbineError> {
+                    for x in data.iter() {
+                        assert!(x.value == seen);
+                        assert!(handled[seen as usize].load(Ordering::SeqCst) == 1);
+                        seen += 1;
+                    }
+                    Ok(())
+                });
+                seen
+            })
+        };
+
+        // The ring is much smaller than `count`, so the writer is gated on the pool
+        for i in 0..count {
+            t.write(TestSlot { value: i as u64 }).unwrap();
+        }
+        drop(t);
+
+        for w in workers {
+            w.join().unwrap();
+        }
+        assert!(downstream.join().unwrap() == count as u64);
+        assert!(handled.iter().all(|h| h.load(Ordering::SeqCst) == 1));
+    }
+
+    #[test]
+    fn test_worker_pool_rejects_finalized_token() {
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+        let read = t.ep_new().unwrap();
+        let work = t.ep_new().unwrap();
+        t.ep_depends(work, read).unwrap();
+
+        let _reader = t.ep_finalize(read).unwrap();
+        assert!(t.worker_pool(read).err() == Some(TurbineError::AlreadyBound(read)));
+
+        // Once the token is a pool, it can't also be finalized on its own
+        let _pool = t.worker_pool(work).unwrap();
+        assert!(t.ep_finalize(work).err() == Some(TurbineError::AlreadyBound(work)));
+        assert!(t.ep_finalize_mut(work).err() == Some(TurbineError::AlreadyBound(work)));
+    }
+
+    #[test]
+    fn test_worker_pool_shared_by_token() {
+        let mut t: Turbine<TestSlot> = Turbine::new(64).unwrap();
+        let work = t.ep_new().unwrap();
+        assert!(t.worker_pool(work + 1).err() == Some(TurbineError::UnknownProcessor(work + 1)));
+
+        let first = t.worker_pool(work).unwrap();
+        let second = t.worker_pool(work).unwrap();
+
+        for i in 0..10 {
+            t.write(TestSlot { value: i }).unwrap();
+        }
+
+        let mut seen = vec![];
+        first.start::<BusyWait>(|_: &TestSlot, sequence: u64| -> Result<(), TurbineError> {
+            seen.push(sequence);
+            if sequence == 4 { Err(TurbineError::Shutdown) } else { Ok(()) }
+        });
+
+        // The second worker picks up where the first stopped, until the stream ends
+        drop(t);
+        second.start::<BusyWait>(|_: &TestSlot, sequence: u64| -> Result<(), TurbineError> {
+            seen.push(sequence);
+            Ok(())
+        });
+        assert!(seen == (0..10).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_worker_pool_exception_handler() {
+        let mut t: Turbine<TestSlot> = Turbine::new(8).unwrap();
+        let work = t.ep_new().unwrap();
+        let after = t.ep_new().unwrap();
+        t.ep_depends(after, work).unwrap();
+
+        let failed = Arc::new(AtomicUsize::new(usize::MAX));
+        let pool = {
+            let failed = failed.clone();
+            t.worker_pool(work).unwrap()
+                .with_exception_handler(move |_: &(dyn Any + Send), sequence: u64, slot: &TestSlot| {
+                    assert!(slot.value == sequence);
+                    failed.store(sequence as usize, Ordering::SeqCst);
+                    ExceptionAction::Skip
+                })
+        };
+
+        let workers: Vec<_> = (0..2).map(|_| {
+            let worker = pool.clone();
+            thread::spawn(move || {
+                worker.start_with(YieldingWait::new(10), |event: &TestSlot, _: u64| -> Result<(), TurbineError> {
+                    if event.value == 13 {
+                        panic!("bad event");
+                    }
+                    Ok(())
+                });
+            })
+        }).collect();
+
+        // The failed sequence is still completed, so the pool keeps draining the ring
+        let event_processor = t.ep_finalize(after).unwrap();
+        let downstream = thread::spawn(move || {
+            let mut seen = 0;
+            event_processor.start_with(YieldingWait::new(10), |data: &[TestSlot]| -> Result<(), TurbineError> {
+                seen += data.len();
+                Ok(())
+            });
+            seen
+        });
+
+        for i in 0..100 {
+            t.write(TestSlot { value: i }).unwrap();
+        }
+        drop(t);
+
+        for w in workers {
+            w.join().unwrap();
+        }
+        assert!(downstream.join().unwrap() == 100);
+        assert!(failed.load(Ordering::SeqCst) == 13);
+    }
+
+    #[test]
+    fn test_worker_pool_poison() {
+        let mut t: Turbine<TestSlot> = Turbine::new(4).unwrap();
+        let work = t.ep_new().unwrap();
+        let pool = t.worker_pool(work).unwrap()
+            .with_exception_handler(|_: &(dyn Any + Send), _: u64, _: &TestSlot| ExceptionAction::Poison);
+
+        for i in 0..4 {
+            t.write(TestSlot { value: i }).unwrap();
+        }
+        pool.start::<BusyWait>(|event: &TestSlot, _: u64| -> Result<(), TurbineError> {
+            assert!(event.value != 2, "bad event");
+            Ok(())
+        });
+
+        // The writer fails rather than waiting forever for the failed sequence
+        assert!(t.control().is_poisoned());
+        assert!(t.write(TestSlot { value: 4 }) == Err(TurbineError::Poisoned));
+    }
+}